@@ -49,7 +49,12 @@ fn main() {
     // The bindgen::Builder is the main entry point
     // to bindgen, and lets you build up options for
     // the resulting bindings.
-    let bindings = bindgen::Builder::default()
+    let mut builder = bindgen::Builder::default();
+    // The Enterprise Edition APIs, like predictive models, are only declared with this defined:
+    if env::var("CARGO_FEATURE_ENTERPRISE").is_ok() {
+        builder = builder.clang_arg("-DCOUCHBASE_ENTERPRISE");
+    }
+    let bindings = builder
         // The input header we would like to generate bindings for.
         .header("src/wrapper.h")
         // C '#include' search paths:
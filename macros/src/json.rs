@@ -0,0 +1,316 @@
+// Compile-time JSON query parser
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+// This checks that a query is valid JSON in the shape of the JSON query schema
+// (github.com/couchbase/couchbase-lite-core/wiki/JSON-Query-Schema), and finds the names of the
+// result columns and parameters.
+
+use super::{QueryInfo, SyntaxError};
+
+
+enum Json {
+    Null,
+    Bool,
+    Number,
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, usize, Json)>),     // Key, offset of key, value
+}
+
+const KEYS: &[&str] = &["WHAT", "WHERE", "FROM", "DISTINCT", "GROUP_BY", "HAVING", "ORDER_BY",
+                        "LIMIT", "OFFSET"];
+
+const META_PROPERTIES: &[&str] = &["_id", "_sequence", "_deleted", "_expiration", "_revisionID"];
+
+
+struct Parser<'s> {
+    source: &'s str,
+    pos:    usize,
+}
+
+type ParseResult<T> = Result<T, SyntaxError>;
+
+impl<'s> Parser<'s> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.source[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.source.as_bytes().get(self.pos).cloned()
+    }
+
+    fn error<T>(&self, message: &str) -> ParseResult<T> {
+        Err(SyntaxError::new(self.pos, message))
+    }
+
+    fn expect(&mut self, c: u8) -> ParseResult<()> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(&format!("expected `{}`", c as char))
+        }
+    }
+
+    fn value(&mut self) -> ParseResult<Json> {
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    if self.peek() != Some(b'"') {
+                        return self.error("expected a string key");
+                    }
+                    let offset = self.pos;
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    members.push((key, offset, self.value()?));
+                    if self.peek() == Some(b',') {
+                        self.pos += 1;
+                    } else {
+                        self.expect(b'}')?;
+                        return Ok(Json::Object(members));
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    if self.peek() == Some(b',') {
+                        self.pos += 1;
+                    } else {
+                        self.expect(b']')?;
+                        return Ok(Json::Array(items));
+                    }
+                }
+            }
+            Some(b'"') => Ok(Json::Str(self.string()?)),
+            Some(b't') | Some(b'f') | Some(b'n') => {
+                for word in &["true", "false", "null"] {
+                    if self.source[self.pos..].starts_with(word) {
+                        self.pos += word.len();
+                        return Ok(if *word == "null" { Json::Null } else { Json::Bool });
+                    }
+                }
+                self.error("expected a JSON value")
+            }
+            Some(c) if c == b'-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.pos < self.source.len() {
+                    let c = self.source.as_bytes()[self.pos];
+                    if !(c.is_ascii_digit() || c == b'-' || c == b'+' || c == b'.' || c == b'e' || c == b'E') {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                if self.source[start..self.pos].parse::<f64>().is_err() {
+                    self.pos = start;
+                    return self.error("invalid number");
+                }
+                Ok(Json::Number)
+            }
+            None => self.error("unexpected end of JSON"),
+            _    => self.error("expected a JSON value"),
+        }
+    }
+
+    fn string(&mut self) -> ParseResult<String> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut text = String::new();
+        let mut chars = self.source[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(text);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => text.push('\n'),
+                    Some((_, 't')) => text.push('\t'),
+                    Some((_, 'r')) => text.push('\r'),
+                    Some((_, 'b')) => text.push('\u{8}'),
+                    Some((_, 'f')) => text.push('\u{c}'),
+                    Some((_, 'u')) => {
+                        let hex: String = (0..4).filter_map(|_| chars.next().map(|(_, c)| c)).collect();
+                        match u32::from_str_radix(&hex, 16).ok().and_then(std::char::from_u32) {
+                            Some(c) => text.push(c),
+                            None    => text.push('\u{FFFD}'),    // (e.g. half a surrogate pair)
+                        }
+                    }
+                    Some((_, c)) => text.push(c),
+                    None => break,
+                },
+                c if (c as u32) < 0x20 => {
+                    self.pos += i;
+                    return self.error("control character in string");
+                }
+                c => text.push(c),
+            }
+        }
+        self.pos = start;
+        self.error("unterminated string")
+    }
+}
+
+
+// Adds the names of the `["$NAME"]` parameters in an expression.
+fn find_parameters(json: &Json, parameters: &mut Vec<String>) {
+    match json {
+        Json::Array(items) => {
+            if let Some(Json::Str(op)) = items.first() {
+                if op.len() > 1 && op.starts_with('$') && !parameters.iter().any(|p| *p == op[1..]) {
+                    parameters.push(op[1..].to_string());
+                }
+            }
+            for item in items {
+                find_parameters(item, parameters);
+            }
+        }
+        Json::Object(members) => {
+            for (_, _, value) in members {
+                find_parameters(value, parameters);
+            }
+        }
+        _ => { }
+    }
+}
+
+// The name of a result column, given its `WHAT` expression.
+fn column_name(expression: &Json, index: usize) -> String {
+    if let Json::Array(items) = expression {
+        match (items.first(), items.get(2)) {
+            (Some(Json::Str(op)), Some(Json::Str(alias))) if op == "AS" => {
+                return alias.clone();
+            }
+            (Some(Json::Str(op)), _) if items.len() == 1 && op.len() > 1 && op.starts_with('.') => {
+                let property = op.rsplit('.').next().unwrap();
+                if META_PROPERTIES.contains(&property) {
+                    return property[1..].to_string();
+                }
+                return property.to_string();
+            }
+            _ => { }
+        }
+    }
+    format!("${}", index)
+}
+
+
+/** Parses a JSON query, returning its column and parameter names. */
+pub fn parse(source: &str) -> Result<QueryInfo, SyntaxError> {
+    let mut parser = Parser{source, pos: 0};
+    let query = parser.value()?;
+    if parser.peek().is_some() {
+        return parser.error("unexpected text after the query");
+    }
+
+    let members = match query {
+        Json::Object(members) => members,
+        _ => return Err(SyntaxError::new(0, "a JSON query must be an object")),
+    };
+    let mut columns = vec!["*".to_string()];
+    let mut parameters = Vec::new();
+    for (key, offset, value) in &members {
+        if !KEYS.contains(&key.as_str()) {
+            return Err(SyntaxError::new(*offset, &format!("unknown query key \"{}\"; expected one of {}",
+                                                          key, KEYS.join(", "))));
+        }
+        if key == "WHAT" {
+            match value {
+                Json::Array(items) => {
+                    columns = items.iter().enumerate().map(|(i, item)| column_name(item, i + 1)).collect();
+                }
+                _ => return Err(SyntaxError::new(*offset, "WHAT must be an array of expressions")),
+            }
+        }
+        find_parameters(value, &mut parameters);
+    }
+    Ok(QueryInfo{columns, parameters})
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(source: &str) -> QueryInfo {
+        match parse(source) {
+            Ok(info) => info,
+            Err(err) => panic!("{:?} failed to parse at {}: {}", source, err.offset, err.message),
+        }
+    }
+
+    fn error(source: &str) -> (usize, String) {
+        match parse(source) {
+            Ok(_)    => panic!("{:?} should not have parsed", source),
+            Err(err) => (err.offset, err.message),
+        }
+    }
+
+    #[test]
+    fn columns() {
+        let info = parsed(r#"{"WHAT": [[".name"], [".address.city"], ["._id"], ["._sequence"],
+                                      ["AS", [".age"], "years"], ["+", [".a"], 1], "name"]}"#);
+        assert_eq!(info.columns, vec!["name", "city", "id", "sequence", "years", "$6", "$7"]);
+        assert_eq!(parsed(r#"{"WHERE": ["=", [".a"], 1]}"#).columns, vec!["*"]);
+        assert!(parsed(r#"{"WHAT": []}"#).columns.is_empty());
+    }
+
+    #[test]
+    fn parameters() {
+        let info = parsed(r#"{"WHAT": [[".a"]],
+                              "WHERE": ["AND", [">", [".a"], ["$min"]],
+                                               ["OR", ["<", [".b"], ["$max"]], ["=", [".c"], ["$min"]]]],
+                              "LIMIT": ["$limit"]}"#);
+        assert_eq!(info.parameters, vec!["min", "max", "limit"]);
+        assert!(parsed(r#"{"WHAT": [["$"], "$x"]}"#).parameters.is_empty());
+    }
+
+    #[test]
+    fn strings() {
+        let info = parsed(r#"{"WHAT": [["AS", [".a"], "tab\t \"quoted\" é"]]}"#);
+        assert_eq!(info.columns, vec!["tab\t \"quoted\" é"]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error(r#"{"WHAT": [[".a"]], "WERE": 1}"#).0, 19);
+        assert!(error(r#"{"WHAT": [[".a"]], "WERE": 1}"#).1.starts_with("unknown query key \"WERE\"; expected one of WHAT, WHERE"));
+        assert_eq!(error(r#"{"WHAT": 1}"#), (1, "WHAT must be an array of expressions".to_string()));
+        assert_eq!(error(r#"[[".a"]]"#), (0, "a JSON query must be an object".to_string()));
+        assert_eq!(error(r#"{"WHAT": [[".a"]"#), (16, "expected `]`".to_string()));
+        assert_eq!(error(r#"{"WHAT": ["#), (10, "unexpected end of JSON".to_string()));
+        assert_eq!(error(r#"{"WHAT": [[".a]]}"#), (11, "unterminated string".to_string()));
+        assert_eq!(error(r#"{"WHAT": [1.2.3]}"#), (10, "invalid number".to_string()));
+        assert_eq!(error(r#"{WHAT: []}"#), (1, "expected a string key".to_string()));
+        assert_eq!(error(r#"{"WHAT": [tru]}"#), (10, "expected a JSON value".to_string()));
+        assert_eq!(error(r#"{"WHAT": []} x"#), (13, "unexpected text after the query".to_string()));
+    }
+}
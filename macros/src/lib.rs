@@ -0,0 +1,373 @@
+// Couchbase Lite procedural macros
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+extern crate proc_macro;
+
+mod json;
+mod n1ql;
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use std::iter::FromIterator;
+
+
+/** The result columns and parameters of a query. */
+pub(crate) struct QueryInfo {
+    pub columns:    Vec<String>,
+    pub parameters: Vec<String>,
+}
+
+/** A syntax error in a query, at a byte offset in its source. */
+#[derive(Debug)]
+pub(crate) struct SyntaxError {
+    pub offset:  usize,
+    pub message: String,
+}
+
+impl SyntaxError {
+    pub fn new(offset: usize, message: &str) -> SyntaxError {
+        SyntaxError{offset, message: message.to_string()}
+    }
+}
+
+
+/** Declares a query whose syntax, column names and parameter names are checked at compile time,
+    with a struct for its result rows:
+    ```ignore
+    cbl_query!(#[derive(Debug)] pub Adults,
+               "SELECT name, age FROM _ WHERE age >= $min ORDER BY name",
+               { name: String, age: i64 });
+
+    let adults = Adults::new(&db)?;
+    for row in adults.set_min(18).execute()? {
+        let row: AdultsRow = row?;
+        println!("{} is {}", row.name, row.age);
+    }
+    ```
+    The query may be N1QL, or JSON if it starts with `{`. The row struct (named after the query,
+    plus `Row`) has a field for each column, in order, named like the column and of a type that
+    implements `FromFleece`; attributes before the name are applied to it. Each `$PARAM` gets a
+    `set_PARAM` method. A column without a property name needs an `AS` alias. */
+#[proc_macro]
+pub fn cbl_query(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(output) => output,
+        Err((span, message)) => compile_error(span, &message),
+    }
+}
+
+
+type ExpandResult<T> = Result<T, (Span, String)>;
+
+struct Field {
+    name:  String,      // Column name it matches, without any `r#`
+    ident: String,      // Field name as written, e.g. `r#type`
+    span:  Span,
+    ty:    String,
+}
+
+fn expand(input: TokenStream) -> ExpandResult<TokenStream> {
+    let mut tokens = input.into_iter().peekable();
+
+    // Attributes and visibility:
+    let mut attributes = String::new();
+    while let Some(TokenTree::Punct(p)) = tokens.peek().cloned() {
+        if p.as_char() != '#' { break; }
+        tokens.next();
+        match tokens.next() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => {
+                attributes += &format!("#{}\n", g);
+            }
+            _ => return Err((p.span(), "expected an attribute after `#`".to_string())),
+        }
+    }
+    let mut visibility = String::new();
+    if let Some(TokenTree::Ident(ident)) = tokens.peek().cloned() {
+        if ident.to_string() == "pub" {
+            tokens.next();
+            visibility.push_str("pub");
+            if let Some(TokenTree::Group(g)) = tokens.peek().cloned() {
+                if g.delimiter() == Delimiter::Parenthesis {
+                    tokens.next();
+                    visibility += &g.to_string();
+                }
+            }
+        }
+    }
+
+    // Name and query source:
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(ident)) => ident.to_string(),
+        other => return Err((span_of(&other), "expected the name of the query type".to_string())),
+    };
+    expect_comma(tokens.next())?;
+    let (source, source_span) = match tokens.next() {
+        Some(TokenTree::Literal(lit)) => match string_literal(&lit.to_string()) {
+            Some(source) => (source, lit.span()),
+            None => return Err((lit.span(), "expected the query as a string literal".to_string())),
+        },
+        other => return Err((span_of(&other), "expected the query as a string literal".to_string())),
+    };
+
+    // Row fields:
+    let mut fields = Vec::new();
+    let mut fields_span = source_span;
+    match tokens.next() {
+        None => { }
+        comma => {
+            expect_comma(comma)?;
+            match tokens.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+                    fields_span = g.span();
+                    fields = parse_fields(g.stream())?;
+                }
+                None => { }
+                other => return Err((span_of(&other), "expected `{` and the row fields".to_string())),
+            }
+            if let Some(TokenTree::Punct(p)) = tokens.peek().cloned() {
+                if p.as_char() == ',' { tokens.next(); }
+            }
+            if let Some(extra) = tokens.next() {
+                return Err((extra.span(), "unexpected tokens after the row fields".to_string()));
+            }
+        }
+    }
+
+    // Check the query:
+    let is_json = source.trim_start().starts_with('{');
+    let info = if is_json { json::parse(&source) } else { n1ql::parse(&source) };
+    let info = info.map_err(|err| {
+        let (line, column) = line_and_column(&source, err.offset);
+        (source_span, format!("invalid {} query, at line {}, column {}: {}",
+                              if is_json {"JSON"} else {"N1QL"}, line, column, err.message))
+    })?;
+
+    // Check the fields against the columns:
+    if info.columns.iter().any(|c| c == "*") {
+        return Err((source_span, "the query selects `*`, so its columns can't be typed; select \
+                                  the properties instead".to_string()));
+    }
+    for (i, column) in info.columns.iter().enumerate() {
+        match fields.get(i) {
+            Some(field) if field.name == *column => { }
+            Some(field) => {
+                let hint = if column.starts_with('$') { "; name the column with `AS`" } else { "" };
+                return Err((field.span, format!("column {} of the query is `{}`, not `{}`{}",
+                                                i + 1, column, field.name, hint)));
+            }
+            None => {
+                return Err((fields_span, format!("the row fields must match the query's columns: {}",
+                                                 info.columns.join(", "))));
+            }
+        }
+    }
+    if let Some(field) = fields.get(info.columns.len()) {
+        return Err((field.span, format!("the query has no column `{}`; its columns are: {}",
+                                        field.name, info.columns.join(", "))));
+    }
+
+    Ok(generate(&name, &attributes, &visibility, &source, is_json, &info, &fields)
+        .parse()
+        .expect("generated code"))
+}
+
+fn parse_fields(stream: TokenStream) -> ExpandResult<Vec<Field>> {
+    let mut fields = Vec::new();
+    let mut tokens = stream.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let (ident, span) = match token {
+            TokenTree::Ident(ident) => (ident.to_string(), ident.span()),
+            other => return Err((other.span(), "expected a field name".to_string())),
+        };
+        match tokens.next() {
+            Some(TokenTree::Punct(ref p)) if p.as_char() == ':' => { }
+            other => return Err((other.map_or(span, |t| t.span()), "expected `:` and the field's type".to_string())),
+        }
+        // The type extends to the next comma that isn't inside `<...>`:
+        let mut ty = Vec::new();
+        let mut depth = 0;
+        while let Some(token) = tokens.peek().cloned() {
+            if let TokenTree::Punct(ref p) = token {
+                match p.as_char() {
+                    ',' if depth == 0 => break,
+                    '<' => depth += 1,
+                    '>' if p.spacing() == Spacing::Alone || depth > 0 => depth -= 1,
+                    _ => { }
+                }
+            }
+            ty.push(token);
+            tokens.next();
+        }
+        if ty.is_empty() {
+            return Err((span, format!("expected a type for `{}`", ident)));
+        }
+        tokens.next();  // comma
+        let name = ident.trim_start_matches("r#").to_string();
+        fields.push(Field{name, ident, span, ty: TokenStream::from_iter(ty).to_string()});
+    }
+    Ok(fields)
+}
+
+fn generate(name: &str, attributes: &str, visibility: &str, source: &str, is_json: bool,
+            info: &QueryInfo, fields: &[Field]) -> String {
+    let row = format!("{}Row", name);
+    let mut code = format!("{attributes}{vis} struct {row} {{\n", attributes = attributes, vis = visibility, row = row);
+    for field in fields {
+        code += &format!("    pub {}: {},\n", field.ident, field.ty);
+    }
+    code += "}\n\n";
+
+    code += &format!("impl ::couchbase_lite::FromRow for {row} {{\n\
+                          fn from_row(row: &::couchbase_lite::Row<'_>) \
+                              -> ::std::result::Result<Self, ::couchbase_lite::PropertyError> {{\n\
+                              Ok({row} {{\n", row = row);
+    for (i, field) in fields.iter().enumerate() {
+        code += &format!("        {}: row.get_as({})?,\n", field.ident, i);
+    }
+    code += "    })\n    }\n}\n\n";
+
+    code += &format!("{vis} struct {name} {{\n    query: ::couchbase_lite::Query,\n}}\n\n\
+                      #[allow(dead_code, non_snake_case)]\n\
+                      impl {name} {{\n\
+                          pub const SOURCE: &'static str = {source:?};\n\
+                          pub const COLUMNS: &'static [&'static str] = &{columns:?};\n\
+                          pub const PARAMETERS: &'static [&'static str] = &{parameters:?};\n\n\
+                          pub fn new(db: &::couchbase_lite::Database) \
+                              -> ::couchbase_lite::Result<Self> {{\n\
+                              let query = ::couchbase_lite::Query::new(db, \
+                                              ::couchbase_lite::QueryLanguage::{language}, Self::SOURCE)?;\n\
+                              Ok({name}{{query}})\n\
+                          }}\n\n\
+                          pub fn query(&self) -> &::couchbase_lite::Query {{\n\
+                              &self.query\n\
+                          }}\n\n\
+                          pub fn execute(&self) \
+                              -> ::couchbase_lite::Result<::couchbase_lite::TypedRows<{row}>> {{\n\
+                              Ok(::couchbase_lite::TypedRows::new(self.query.execute()?))\n\
+                          }}\n",
+                     vis = visibility, name = name, row = row, source = source,
+                     columns = info.columns, parameters = info.parameters,
+                     language = if is_json {"JSON"} else {"N1QL"});
+    for parameter in &info.parameters {
+        code += &format!("\n    pub fn set_{p}<T: ::couchbase_lite::ToFleece>(&self, value: T) -> &Self {{\n\
+                              self.query.bind({p:?}, value);\n\
+                              self\n\
+                          }}\n", p = parameter);
+    }
+    code += "}\n";
+    code
+}
+
+
+fn expect_comma(token: Option<TokenTree>) -> ExpandResult<()> {
+    match token {
+        Some(TokenTree::Punct(ref p)) if p.as_char() == ',' => Ok(()),
+        other => Err((span_of(&other), "expected `,`".to_string())),
+    }
+}
+
+fn span_of(token: &Option<TokenTree>) -> Span {
+    token.as_ref().map_or_else(Span::call_site, |t| t.span())
+}
+
+// Converts a byte offset in the query source to a 1-based line and column, for error messages.
+fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let line = source[..offset].matches('\n').count() + 1;
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    (line, source[line_start..offset].chars().count() + 1)
+}
+
+// Decodes the source form of a string literal, plain or raw.
+fn string_literal(literal: &str) -> Option<String> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.find('"')?;
+        return Some(raw.get(hashes + 1 .. raw.len() - hashes - 1)?.to_string());
+    }
+    let body = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n'  => out.push('\n'),
+            't'  => out.push('\t'),
+            'r'  => out.push('\r'),
+            '0'  => out.push('\0'),
+            'x'  => {
+                let hex: String = chars.by_ref().take(2).collect();
+                out.push(u8::from_str_radix(&hex, 16).ok()? as char);
+            }
+            'u'  => {
+                let hex: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                out.push(std::char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            }
+            '\n' => {
+                // Line continuation: skip the newline and leading whitespace.
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+            }
+            c    => out.push(c),
+        }
+    }
+    Some(out)
+}
+
+// Returns `compile_error!("message");`, reported at the given span.
+fn compile_error(span: Span, message: &str) -> TokenStream {
+    let mut literal = Literal::string(message);
+    literal.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut group = Group::new(Delimiter::Parenthesis, TokenStream::from(TokenTree::Literal(literal)));
+    group.set_span(span);
+    let mut semicolon = Punct::new(';', Spacing::Alone);
+    semicolon.set_span(span);
+    TokenStream::from_iter(vec![TokenTree::Ident(Ident::new("compile_error", span)),
+                                TokenTree::Punct(bang),
+                                TokenTree::Group(group),
+                                TokenTree::Punct(semicolon)])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn string_literals() {
+        assert_eq!(string_literal(r#""SELECT a FROM _""#).as_deref(), Some("SELECT a FROM _"));
+        assert_eq!(string_literal(r#""a\n\t\"b\" \x41 \u{e9}""#).as_deref(), Some("a\n\t\"b\" A é"));
+        assert_eq!(string_literal("\"a \\\n      b\"").as_deref(), Some("a b"));
+        assert_eq!(string_literal(r###"r#"{"WHAT": [[".a"]]}"#"###).as_deref(), Some(r#"{"WHAT": [[".a"]]}"#));
+        assert_eq!(string_literal(r#"r"raw\n""#).as_deref(), Some("raw\\n"));
+        assert_eq!(string_literal("42"), None);
+        assert_eq!(string_literal("b\"bytes\""), None);
+    }
+
+    #[test]
+    fn error_positions() {
+        let source = "SELECT a\nFROM _\nWHERE é = $";
+        assert_eq!(line_and_column(source, 0), (1, 1));
+        assert_eq!(line_and_column(source, 7), (1, 8));
+        assert_eq!(line_and_column(source, 9), (2, 1));
+        assert_eq!(line_and_column(source, source.len() - 1), (3, 11));
+        assert_eq!(line_and_column(source, source.len()), (3, 12));
+    }
+}
@@ -0,0 +1,623 @@
+// Compile-time N1QL parser
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+// This checks the syntax of the N1QL subset Couchbase Lite supports, and finds the names of the
+// result columns and parameters. It doesn't check function names or types; Couchbase Lite
+// itself does that when the query is compiled at runtime.
+
+use super::{QueryInfo, SyntaxError};
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),           // Identifier or keyword
+    Quoted(String),         // `Quoted identifier`
+    Number,
+    Str,
+    Param(String),
+    Punct(&'static str),
+    End,
+}
+
+const PUNCTUATION: &[&str] = &["==", "!=", "<>", "<=", ">=", "||",
+                               "(", ")", "[", "]", "{", "}", ",", ".", ":", ";",
+                               "*", "+", "-", "/", "%", "=", "<", ">"];
+
+const RESERVED: &[&str] = &["ALL", "AND", "ANY", "AS", "ASC", "BETWEEN", "BY", "CASE", "COLLATE",
+                            "CROSS", "DESC", "DISTINCT", "ELSE", "END", "EVERY", "FALSE", "FROM",
+                            "GROUP", "HAVING", "IN", "INNER", "IS", "JOIN", "LEFT", "LIKE", "LIMIT",
+                            "MISSING", "NOT", "NULL", "OFFSET", "ON", "OR", "ORDER", "OUTER",
+                            "SATISFIES", "SELECT", "SOME", "THEN", "TRUE", "VALUED", "WHEN",
+                            "WHERE"];
+
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, SyntaxError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        } else if source[i..].starts_with("--") {
+            i = source[i..].find('\n').map_or(bytes.len(), |n| i + n);
+            continue;
+        } else if source[i..].starts_with("/*") {
+            i = source[i+2..].find("*/").map(|n| i + 2 + n + 2)
+                             .ok_or_else(|| SyntaxError::new(start, "unterminated comment"))?;
+            continue;
+        } else if c == b'\'' || c == b'"' || c == b'`' {
+            i += 1;
+            let mut text = String::new();
+            loop {
+                match source[i..].chars().next() {
+                    None => return Err(SyntaxError::new(start, "unterminated string or identifier")),
+                    Some('\\') if c != b'`' => {
+                        i += 1;
+                        if let Some(escaped) = source[i..].chars().next() {
+                            text.push(escaped);
+                            i += escaped.len_utf8();
+                        }
+                    }
+                    Some(d) if d as u32 == c as u32 => {
+                        i += 1;
+                        if i < bytes.len() && bytes[i] == c {
+                            text.push(d);       // Doubled quote
+                            i += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Some(d) => {
+                        text.push(d);
+                        i += d.len_utf8();
+                    }
+                }
+            }
+            tokens.push((if c == b'`' { Token::Quoted(text) } else { Token::Str }, start));
+        } else if c.is_ascii_digit() || (c == b'.' && bytes.get(i+1).is_some_and(u8::is_ascii_digit)) {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.'
+                                      || ((bytes[i] == b'+' || bytes[i] == b'-')
+                                          && (bytes[i-1] == b'e' || bytes[i-1] == b'E'))) {
+                i += 1;
+            }
+            let number = &source[start..i];
+            if number.parse::<f64>().is_err() {
+                return Err(SyntaxError::new(start, &format!("invalid number `{}`", number)));
+            }
+            tokens.push((Token::Number, start));
+        } else if c == b'$' {
+            i += 1;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            if i == start + 1 {
+                return Err(SyntaxError::new(start, "expected a parameter name after `$`"));
+            }
+            tokens.push((Token::Param(source[start+1..i].to_string()), start));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push((Token::Word(source[start..i].to_string()), start));
+        } else if let Some(p) = PUNCTUATION.iter().find(|p| source[i..].starts_with(*p)) {
+            i += p.len();
+            tokens.push((Token::Punct(p), start));
+        } else {
+            let c = source[i..].chars().next().unwrap();
+            return Err(SyntaxError::new(start, &format!("unexpected character `{}`", c)));
+        }
+    }
+    tokens.push((Token::End, source.len()));
+    Ok(tokens)
+}
+
+
+struct Parser {
+    tokens:     Vec<(Token, usize)>,
+    pos:        usize,
+    parameters: Vec<String>,
+}
+
+type ParseResult<T> = Result<T, SyntaxError>;
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Token::Word(w) => w.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn punct(&mut self, punct: &str) -> bool {
+        match *self.peek() {
+            Token::Punct(p) if p == punct => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn error<T>(&self, expected: &str) -> ParseResult<T> {
+        let found = match self.peek() {
+            Token::Word(w)      => format!("`{}`", w),
+            Token::Quoted(w)    => format!("`{}`", w),
+            Token::Number       => "a number".to_string(),
+            Token::Str          => "a string".to_string(),
+            Token::Param(p)     => format!("`${}`", p),
+            Token::Punct(p)     => format!("`{}`", p),
+            Token::End          => "the end of the query".to_string(),
+        };
+        Err(SyntaxError::new(self.tokens[self.pos].1, &format!("expected {}, found {}", expected, found)))
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> ParseResult<()> {
+        if self.keyword(keyword) { Ok(()) } else { self.error(&format!("`{}`", keyword)) }
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> ParseResult<()> {
+        if self.punct(punct) { Ok(()) } else { self.error(&format!("`{}`", punct)) }
+    }
+
+    // An identifier that isn't a reserved word.
+    fn identifier(&mut self) -> Option<String> {
+        match self.peek().clone() {
+            Token::Word(ref w) if !RESERVED.iter().any(|r| r.eq_ignore_ascii_case(w)) => {
+                self.pos += 1;
+                Some(w.clone())
+            }
+            Token::Quoted(w) => {
+                self.pos += 1;
+                Some(w)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect_identifier(&mut self) -> ParseResult<String> {
+        match self.identifier() {
+            Some(id) => Ok(id),
+            None     => self.error("an identifier"),
+        }
+    }
+
+    //////// STATEMENT:
+
+    fn select(&mut self) -> ParseResult<Vec<String>> {
+        self.expect_keyword("SELECT")?;
+        if !self.keyword("DISTINCT") {
+            self.keyword("ALL");
+        }
+        let mut columns = Vec::new();
+        loop {
+            let index = columns.len() + 1;
+            columns.push(self.result_column(index)?);
+            if !self.punct(",") { break; }
+        }
+        if self.keyword("FROM") {
+            self.data_source()?;
+            while self.is_keyword("JOIN") || self.is_keyword("LEFT") || self.is_keyword("INNER")
+                    || self.is_keyword("CROSS") {
+                let cross = self.keyword("CROSS");
+                if !cross && self.keyword("LEFT") {
+                    self.keyword("OUTER");
+                } else if !cross {
+                    self.keyword("INNER");
+                }
+                self.expect_keyword("JOIN")?;
+                self.data_source()?;
+                if !cross {
+                    self.expect_keyword("ON")?;
+                    self.expression()?;
+                }
+            }
+        }
+        if self.keyword("WHERE") {
+            self.expression()?;
+        }
+        if self.keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            self.expression_list()?;
+            if self.keyword("HAVING") {
+                self.expression()?;
+            }
+        }
+        if self.keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            loop {
+                self.expression()?;
+                if !self.keyword("ASC") {
+                    self.keyword("DESC");
+                }
+                if !self.punct(",") { break; }
+            }
+        }
+        if self.keyword("LIMIT") {
+            self.expression()?;
+        }
+        if self.keyword("OFFSET") {
+            self.expression()?;
+        }
+        self.punct(";");
+        if *self.peek() != Token::End {
+            return self.error("the end of the query");
+        }
+        Ok(columns)
+    }
+
+    // Parses a result column, returning its name.
+    fn result_column(&mut self, index: usize) -> ParseResult<String> {
+        if self.punct("*") {
+            return Ok("*".to_string());
+        }
+        let path = self.expression()?;
+        if self.keyword("AS") {
+            return self.expect_identifier();
+        } else if let Some(alias) = self.identifier() {
+            return Ok(alias);
+        }
+        // A property path, including `meta().id`, is named after its last component:
+        Ok(path.unwrap_or_else(|| format!("${}", index)))
+    }
+
+    fn data_source(&mut self) -> ParseResult<()> {
+        self.expect_identifier()?;
+        while self.punct(".") {
+            self.expect_identifier()?;
+        }
+        if self.keyword("AS") {
+            self.expect_identifier()?;
+        } else {
+            self.identifier();
+        }
+        Ok(())
+    }
+
+    fn expression_list(&mut self) -> ParseResult<()> {
+        loop {
+            self.expression()?;
+            if !self.punct(",") { return Ok(()); }
+        }
+    }
+
+    //////// EXPRESSIONS:
+
+    // Each level returns the last component of the property path it parsed, if the expression
+    // is just a property path; that's how Couchbase Lite names a result column.
+
+    fn expression(&mut self) -> ParseResult<Option<String>> {
+        let mut path = self.and()?;
+        while self.keyword("OR") {
+            self.and()?;
+            path = None;
+        }
+        Ok(path)
+    }
+
+    fn and(&mut self) -> ParseResult<Option<String>> {
+        let mut path = self.not()?;
+        while self.keyword("AND") {
+            self.not()?;
+            path = None;
+        }
+        Ok(path)
+    }
+
+    fn not(&mut self) -> ParseResult<Option<String>> {
+        if self.keyword("NOT") {
+            self.not()?;
+            return Ok(None);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> ParseResult<Option<String>> {
+        let path = self.concatenation()?;
+        for op in &["==", "!=", "<>", "<=", ">=", "=", "<", ">"] {
+            if self.punct(op) {
+                self.concatenation()?;
+                return Ok(None);
+            }
+        }
+        if self.keyword("IS") {
+            self.keyword("NOT");
+            if !(self.keyword("NULL") || self.keyword("MISSING") || self.keyword("VALUED")) {
+                self.concatenation()?;
+            }
+            return Ok(None);
+        }
+        let negated = self.keyword("NOT");
+        if self.keyword("LIKE") || self.keyword("IN") {
+            self.concatenation()?;
+        } else if self.keyword("BETWEEN") {
+            self.concatenation()?;
+            self.expect_keyword("AND")?;
+            self.concatenation()?;
+        } else if negated {
+            return self.error("`LIKE`, `IN` or `BETWEEN`");
+        } else {
+            return Ok(path);
+        }
+        Ok(None)
+    }
+
+    fn concatenation(&mut self) -> ParseResult<Option<String>> {
+        let mut path = self.additive()?;
+        while self.punct("||") {
+            self.additive()?;
+            path = None;
+        }
+        Ok(path)
+    }
+
+    fn additive(&mut self) -> ParseResult<Option<String>> {
+        let mut path = self.multiplicative()?;
+        while self.punct("+") || self.punct("-") {
+            self.multiplicative()?;
+            path = None;
+        }
+        Ok(path)
+    }
+
+    fn multiplicative(&mut self) -> ParseResult<Option<String>> {
+        let mut path = self.unary()?;
+        while self.punct("*") || self.punct("/") || self.punct("%") {
+            self.unary()?;
+            path = None;
+        }
+        Ok(path)
+    }
+
+    fn unary(&mut self) -> ParseResult<Option<String>> {
+        if self.punct("-") || self.punct("+") {
+            self.unary()?;
+            return Ok(None);
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> ParseResult<Option<String>> {
+        let mut path = self.primary()?;
+        loop {
+            if self.punct(".") {
+                path = Some(self.expect_identifier()?);
+            } else if self.punct("[") {
+                self.expression()?;
+                self.expect_punct("]")?;
+            } else if self.keyword("COLLATE") {
+                if self.punct("(") {
+                    while !self.punct(")") {
+                        self.expect_identifier()?;
+                    }
+                } else {
+                    self.expect_identifier()?;
+                }
+                path = None;
+            } else {
+                return Ok(path);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> ParseResult<Option<String>> {
+        match self.peek().clone() {
+            Token::Number | Token::Str => {
+                self.advance();
+                return Ok(None);
+            }
+            Token::Param(name) => {
+                self.advance();
+                if !self.parameters.contains(&name) {
+                    self.parameters.push(name);
+                }
+                return Ok(None);
+            }
+            _ => { }
+        }
+        if self.keyword("TRUE") || self.keyword("FALSE") || self.keyword("NULL") || self.keyword("MISSING") {
+            return Ok(None);
+        }
+        if self.punct("(") {
+            self.expression()?;
+            self.expect_punct(")")?;
+            return Ok(None);
+        }
+        if self.punct("[") {
+            if !self.punct("]") {
+                self.expression_list()?;
+                self.expect_punct("]")?;
+            }
+            return Ok(None);
+        }
+        if self.punct("{") {
+            if !self.punct("}") {
+                loop {
+                    if *self.peek() == Token::Str {
+                        self.advance();
+                    } else {
+                        self.expect_identifier()?;
+                    }
+                    self.expect_punct(":")?;
+                    self.expression()?;
+                    if !self.punct(",") { break; }
+                }
+                self.expect_punct("}")?;
+            }
+            return Ok(None);
+        }
+        if self.keyword("CASE") {
+            if !self.is_keyword("WHEN") {
+                self.expression()?;
+            }
+            self.expect_keyword("WHEN")?;
+            loop {
+                self.expression()?;
+                self.expect_keyword("THEN")?;
+                self.expression()?;
+                if !self.keyword("WHEN") { break; }
+            }
+            if self.keyword("ELSE") {
+                self.expression()?;
+            }
+            self.expect_keyword("END")?;
+            return Ok(None);
+        }
+        if self.keyword("ANY") || self.keyword("SOME") || self.keyword("EVERY") {
+            if self.keyword("AND") {
+                self.expect_keyword("EVERY")?;
+            }
+            self.expect_identifier()?;
+            self.expect_keyword("IN")?;
+            self.expression()?;
+            self.expect_keyword("SATISFIES")?;
+            self.expression()?;
+            self.expect_keyword("END")?;
+            return Ok(None);
+        }
+
+        let name = self.expect_identifier().or_else(|_| self.error("an expression"))?;
+        if self.punct("(") {
+            // Function call:
+            if !self.punct(")") {
+                if !self.punct("*") {
+                    self.keyword("DISTINCT");
+                    self.expression_list()?;
+                }
+                self.expect_punct(")")?;
+            }
+            return Ok(None);
+        }
+        Ok(Some(name))
+    }
+}
+
+
+/** Parses a N1QL query, returning its column and parameter names. */
+pub fn parse(source: &str) -> Result<QueryInfo, SyntaxError> {
+    let mut parser = Parser{tokens: tokenize(source)?, pos: 0, parameters: Vec::new()};
+    let columns = parser.select()?;
+    Ok(QueryInfo{columns, parameters: parser.parameters})
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(source: &str) -> QueryInfo {
+        match parse(source) {
+            Ok(info) => info,
+            Err(err) => panic!("{:?} failed to parse at {}: {}", source, err.offset, err.message),
+        }
+    }
+
+    fn error(source: &str) -> (usize, String) {
+        match parse(source) {
+            Ok(_)    => panic!("{:?} should not have parsed", source),
+            Err(err) => (err.offset, err.message),
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        let tokens = tokenize("SELECT `a b`, 'it''s', \"x\\\"y\", 1.5e-3, $p1 -- comment\n /* c */ x<=y").unwrap();
+        assert_eq!(tokens, vec![(Token::Word("SELECT".to_string()), 0),
+                                (Token::Quoted("a b".to_string()), 7),
+                                (Token::Punct(","), 12),
+                                (Token::Str, 14),
+                                (Token::Punct(","), 21),
+                                (Token::Str, 23),
+                                (Token::Punct(","), 29),
+                                (Token::Number, 31),
+                                (Token::Punct(","), 37),
+                                (Token::Param("p1".to_string()), 39),
+                                (Token::Word("x".to_string()), 63),
+                                (Token::Punct("<="), 64),
+                                (Token::Word("y".to_string()), 66),
+                                (Token::End, 67)]);
+        assert_eq!(tokenize("a != b <> c").unwrap()[1].0, Token::Punct("!="));
+        assert_eq!(tokenize("a || .5").unwrap()[2].0, Token::Number);
+    }
+
+    #[test]
+    fn columns() {
+        let info = parsed("SELECT name, address.city, meta().id, count(*), age + 1, price AS cost, \
+                           n.x total, `odd name` FROM _");
+        assert_eq!(info.columns, vec!["name", "city", "id", "$4", "$5", "cost", "total", "odd name"]);
+        assert_eq!(parsed("select distinct a, (b) from db as d").columns, vec!["a", "$2"]);
+        assert_eq!(parsed("SELECT * FROM _").columns, vec!["*"]);
+        assert_eq!(parsed("SELECT a COLLATE NOCASE AS a FROM _ ORDER BY a;").columns, vec!["a"]);
+    }
+
+    #[test]
+    fn parameters() {
+        let info = parsed("SELECT a FROM _ WHERE a > $min AND b < $max OR c IN [$min, $other] \
+                           LIMIT $limit OFFSET $offset");
+        assert_eq!(info.parameters, vec!["min", "max", "other", "limit", "offset"]);
+        assert!(parsed("SELECT a FROM _ WHERE a = '$notparam'").parameters.is_empty());
+    }
+
+    #[test]
+    fn clauses() {
+        parsed("SELECT d.name, count(*) AS n FROM _ AS d JOIN _ AS e ON e.owner = meta(d).id \
+                LEFT OUTER JOIN _ f ON f.x = d.x \
+                WHERE d.age BETWEEN 18 AND 65 AND d.name NOT LIKE 'x%' AND d.email IS NOT VALUED \
+                AND ANY t IN d.tags SATISFIES t = 'a' END \
+                GROUP BY d.name HAVING count(*) > 1 ORDER BY n DESC, d.name LIMIT 10 OFFSET 5");
+        parsed("SELECT CASE WHEN a > 1 THEN 'big' ELSE 'small' END AS size, {'k': a, v: [1, 2]} AS o FROM _");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("SELEC name FROM _"), (0, "expected `SELECT`, found `SELEC`".to_string()));
+        assert_eq!(error("SELECT name FORM _"), (17, "expected the end of the query, found `_`".to_string()));
+        assert_eq!(error("SELECT name FROM _ WHERE"),
+                   (24, "expected an expression, found the end of the query".to_string()));
+        assert_eq!(error("SELECT a FROM _ WHERE a NOT = 1"),
+                   (28, "expected `LIKE`, `IN` or `BETWEEN`, found `=`".to_string()));
+        assert_eq!(error("SELECT count(*) AS FROM _"), (19, "expected an identifier, found `FROM`".to_string()));
+        assert_eq!(error("SELECT 'abc FROM _"), (7, "unterminated string or identifier".to_string()));
+        assert_eq!(error("SELECT a FROM _ WHERE a = $"), (26, "expected a parameter name after `$`".to_string()));
+        assert_eq!(error("SELECT a /* FROM _"), (9, "unterminated comment".to_string()));
+        assert_eq!(error("SELECT 1x FROM _"), (7, "invalid number `1x`".to_string()));
+        assert_eq!(error("SELECT a FROM _ WHERE a ? 1"), (24, "unexpected character `?`".to_string()));
+    }
+}
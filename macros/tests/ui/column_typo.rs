@@ -0,0 +1,5 @@
+#[macro_use] extern crate couchbase_lite_macros;
+
+cbl_query!(People, "SELECT name, age FROM _ WHERE age >= $min", { name: String, aeg: i64 });
+
+fn main() { }
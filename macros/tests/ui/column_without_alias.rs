@@ -0,0 +1,5 @@
+#[macro_use] extern crate couchbase_lite_macros;
+
+cbl_query!(Counts, "SELECT name, count(*) FROM _ GROUP BY name", { name: String, count: i64 });
+
+fn main() { }
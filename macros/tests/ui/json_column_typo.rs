@@ -0,0 +1,5 @@
+#[macro_use] extern crate couchbase_lite_macros;
+
+cbl_query!(Names, r#"{"WHAT": [[".name"], ["AS", [".age"], "years"]]}"#, { name: String, age: i64 });
+
+fn main() { }
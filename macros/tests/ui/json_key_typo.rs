@@ -0,0 +1,5 @@
+#[macro_use] extern crate couchbase_lite_macros;
+
+cbl_query!(Names, r#"{"WHAT": [[".name"]], "WERE": ["=", [".type"], "person"]}"#, { name: String });
+
+fn main() { }
@@ -0,0 +1,5 @@
+#[macro_use] extern crate couchbase_lite_macros;
+
+cbl_query!(Names, "SELEC name FROM _", { name: String });
+
+fn main() { }
@@ -0,0 +1,7 @@
+#[macro_use] extern crate couchbase_lite_macros;
+
+cbl_query!(Adults, "SELECT name
+                    FROM _
+                    WHERE age >= $", { name: String });
+
+fn main() { }
@@ -20,8 +20,10 @@ use super::slice::*;
 use super::error::*;
 use super::c_api::*;
 
+use std::collections::HashMap;
 use std::path::*;
 use std::ptr;
+use std::sync::{Arc, Mutex, RwLock};
 
 
 /** Database configuration options. */
@@ -48,7 +50,17 @@ type ChangeListener = fn(db: &Database, doc_ids: Vec<String>);
 
 /** A connection to an open database. */
 pub struct Database {
-    pub(crate) _ref: *mut CBLDatabase
+    pub(crate) _ref: *mut CBLDatabase,
+    pub(crate) _extras: Arc<DatabaseExtras>
+}
+
+
+/** Rust-side state belonging to a `Database`, shared by all of its clones. */
+#[derive(Default)]
+pub(crate) struct DatabaseExtras {
+    pub validators: RwLock<HashMap<String, Arc<dyn Validator>>>,
+    pub id_generator: RwLock<Option<Arc<dyn IdGenerator>>>,
+    pub query_cache: Mutex<QueryCache>,
 }
 
 
@@ -83,7 +95,7 @@ impl Database {
         if db_ref.is_null() {
             return failure(err);
         }
-        return Ok(Database{_ref: db_ref});
+        return Ok(Database{_ref: db_ref, _extras: Arc::new(DatabaseExtras::default())});
     }
 
 
@@ -119,9 +131,18 @@ impl Database {
     //////// OPERATIONS:
 
 
+    /** Closes the database. This closes it for all clones of this `Database`, and clears its
+        query cache. */
+    pub fn close(self) -> Result<()> {
+        self.clear_query_cache();
+        unsafe { check_bool(|error| CBLDatabase_Close(self._ref, error)) }
+    }
+
+
     /** Closes and deletes a database. If there are any other connections to the database,
         an error is returned. */
     pub fn delete(self) -> Result<()> {
+        self.clear_query_cache();
         unsafe { check_bool(|error| CBLDatabase_Delete(self._ref, error)) }
     }
 
@@ -140,23 +161,24 @@ impl Database {
                 the transaction ends.
          - Transactions can nest. Changes are not committed until the outer one ends. */
    pub fn in_transaction<T>(&self, callback: fn()->T) -> Result<T> {
-        let mut err = CBLError::default();
-        unsafe {
-            if ! CBLDatabase_BeginTransaction(self._ref, &mut err) {
-                return failure(err);
-            }
-        }
+        self.begin_transaction()?;
         let result = callback();
         // TODO: Allow failure in callback, and abort transaction
-        unsafe {
-            if ! CBLDatabase_EndTransaction(self._ref, true, &mut err) {
-                return failure(err);
-            }
-        }
+        self.end_transaction(true)?;
         return Ok(result);
     }
 
 
+    pub(crate) fn begin_transaction(&self) -> Result<()> {
+        unsafe { check_bool(|error| CBLDatabase_BeginTransaction(self._ref, error)) }
+    }
+
+
+    pub(crate) fn end_transaction(&self, commit: bool) -> Result<()> {
+        unsafe { check_bool(|error| CBLDatabase_EndTransaction(self._ref, commit, error)) }
+    }
+
+
     //////// ACCESSORS:
 
 
@@ -224,7 +246,7 @@ impl Drop for Database {
 impl Clone for Database {
     fn clone(&self) -> Self {
         unsafe {
-            return Database{_ref: retain(self._ref)}
+            return Database{_ref: retain(self._ref), _extras: self._extras.clone()}
         }
     }
 }
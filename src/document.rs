@@ -19,10 +19,17 @@ use super::*;
 use super::slice::*;
 use super::c_api::*;
 
+use std::cmp::Ordering;
+use std::fmt;
+use std::ptr;
+use std::time::Duration;
+
 
 /** An in-memory copy of a document. */
 pub struct Document {
-    _ref: *mut CBLDocument
+    _ref:  *mut CBLDocument,
+    _db:   *mut CBLDatabase,        // Database it was read from or saved to, or null
+    flags: CBLDocumentFlags,        // Revision flags, if it came from a replicator callback
 }
 
 
@@ -30,6 +37,7 @@ pub struct Document {
 
 
 /** Conflict-handling options when saving or deleting a document. */
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ConcurrencyControl {
     LastWriteWins  = kCBLConcurrencyControlLastWriteWins as isize,
     FailOnConflict = kCBLConcurrencyControlFailOnConflict as isize
@@ -57,7 +65,7 @@ impl Database {
                     return Err(Error::cbl_error(CouchbaseLiteError::NotFound));
                 }
             }
-            return Ok(Document{_ref: doc});
+            return Ok(Document::adopt(doc, self._ref, 0));
         }
     }
 
@@ -65,17 +73,24 @@ impl Database {
         If a conflicting revision has been saved since `doc` was loaded, the `concurrency`
         parameter specifies whether the save should fail, or the conflicting revision should
         be overwritten with the revision being saved.
-        If you need finer-grained control, call `save_document_resolving` instead. */
+        If you need finer-grained control, call `save_document_resolving` instead.
+        If a validator is registered for the document's type, and the document doesn't pass it,
+        the save fails with a `ValidationError`. */
     pub fn save_document(&mut self,
                          doc: &mut Document,
                          concurrency: ConcurrencyControl)
                          -> Result<()>
     {
+        self.validate_document(doc)?;
         let c_concurrency = concurrency as u8;
         unsafe {
-            return check_bool(|error| CBLDatabase_SaveDocumentWithConcurrencyControl(
-                                            self._ref, doc._ref, c_concurrency, error))
+            check_bool(|error| CBLDatabase_SaveDocumentWithConcurrencyControl(
+                                            self._ref, doc._ref, c_concurrency, error))?;
+            if doc._db.is_null() {
+                doc._db = retain(self._ref);
+            }
         }
+        return Ok(());
     }
 
     /** Saves a new or modified document to the database. This function is the same as
@@ -89,6 +104,52 @@ impl Database {
         todo!()
     }
 
+    /** Performs an optimistic read-modify-write of a document.
+        The document is loaded, its mutable properties are passed to `modify`, and it's saved with
+        `ConcurrencyControl::FailOnConflict`. If another writer saved the document in the meantime,
+        the latest revision is reloaded and `modify` is called again, up to `max_retries` times.
+        Returns the saved document. An error returned by `modify` aborts the update. */
+    pub fn update_document<F>(&mut self,
+                              id: &str,
+                              max_retries: u32,
+                              mut modify: F)
+                              -> Result<Document>
+        where F: FnMut(&mut MutableDict) -> Result<()>
+    {
+        let mut retries = 0;
+        loop {
+            let mut doc = self.get_document(id)?;
+            {
+                let mut props = doc.mutable_properties();
+                modify(&mut props)?;
+            }
+            match self.save_document(&mut doc, ConcurrencyControl::FailOnConflict) {
+                Ok(()) => return Ok(doc),
+                Err(err) => {
+                    if err.code != ErrorCode::CouchbaseLite(CouchbaseLiteError::Conflict)
+                            || retries >= max_retries {
+                        return Err(err);
+                    }
+                }
+            }
+            retries += 1;
+        }
+    }
+
+    /** Deletes a document from the database. (Deletions are replicated, unlike purges.)
+        The `concurrency` parameter works as in `save_document`. */
+    pub fn delete_document(&mut self,
+                           doc: &Document,
+                           concurrency: ConcurrencyControl)
+                           -> Result<()>
+    {
+        let c_concurrency = concurrency as u8;
+        unsafe {
+            return check_bool(|error| CBLDatabase_DeleteDocumentWithConcurrencyControl(
+                                            self._ref, doc._ref, c_concurrency, error))
+        }
+    }
+
     pub fn purge_document_by_id(&mut self, id: &str) -> Result<()> {
         unsafe {
             return check_bool(|error| CBLDatabase_PurgeDocumentByID(self._ref, as_slice(id), error));
@@ -99,17 +160,7 @@ impl Database {
         Documents don't normally expire; you have to call `set_document_expiration`
         to set a document's expiration time. */
     pub fn document_expiration(&self, doc_id: &str) -> Result<Option<Timestamp>> {
-        unsafe {
-            let mut error = CBLError::default();
-            let exp = CBLDatabase_GetDocumentExpiration(self._ref, as_slice(doc_id), &mut error);
-            if exp < 0 {
-                return failure(error);
-            } else if exp == 0 {
-                return Ok(None);
-            } else {
-                return Ok(Some(Timestamp(exp)));
-            }
-        }
+        unsafe { get_expiration(self._ref, doc_id) }
     }
 
     /** Sets or clears the expiration time of a document. */
@@ -123,6 +174,11 @@ impl Database {
         }
     }
 
+    /** Sets a document to expire, and be purged, once the given amount of time has passed. */
+    pub fn expire_document_after(&mut self, doc_id: &str, after: Duration) -> Result<()> {
+        self.set_document_expiration(doc_id, Some(Timestamp::now() + after))
+    }
+
     /** Registers a document change listener callback. It will be called after a specific document
         is changed on disk. */
     pub fn add_document_change_listener(&self, _doc_id: &str, _listener: ChangeListener) -> ListenerToken {
@@ -132,6 +188,188 @@ impl Database {
 }
 
 
+//////// BULK DOCUMENT API:
+
+
+impl Database {
+    /** Saves a batch of documents, in a single transaction. Returns the result of saving each
+        document; a document that fails to save doesn't stop the others from being saved. */
+    pub fn save_documents(&mut self,
+                          docs: &mut [Document],
+                          concurrency: ConcurrencyControl)
+                          -> Vec<Result<()>>
+    {
+        self.batch(docs.len(), false, |db, i| db.save_document(&mut docs[i], concurrency))
+    }
+
+    /** Saves a batch of documents, in a single transaction. If any document fails to save,
+        the transaction is aborted, so none of them are saved, and the first error is returned.
+        (The in-memory `Document`s saved before the failure still have their new revision IDs.) */
+    pub fn save_documents_atomically(&mut self,
+                                     docs: &mut [Document],
+                                     concurrency: ConcurrencyControl)
+                                     -> Result<()>
+    {
+        self.batch(docs.len(), true, |db, i| db.save_document(&mut docs[i], concurrency))
+            .into_iter().collect()
+    }
+
+    /** Reads a batch of documents, in a single transaction so that they're consistent with
+        each other. Returns the result of reading each document. */
+    pub fn get_documents(&self, ids: &[&str]) -> Vec<Result<Document>> {
+        if let Err(err) = self.begin_transaction() {
+            return ids.iter().map(|_| Err(err.clone())).collect();
+        }
+        let docs = ids.iter().map(|id| self.get_document(id)).collect();
+        if let Err(err) = self.end_transaction(true) {
+            return ids.iter().map(|_| Err(err.clone())).collect();
+        }
+        return docs;
+    }
+
+    /** Deletes a batch of documents, in a single transaction. Returns the result of deleting
+        each document; a document that fails to be deleted doesn't stop the others. */
+    pub fn delete_documents(&mut self,
+                            docs: &[Document],
+                            concurrency: ConcurrencyControl)
+                            -> Vec<Result<()>>
+    {
+        self.batch(docs.len(), false, |db, i| db.delete_document(&docs[i], concurrency))
+    }
+
+    /** Deletes a batch of documents, in a single transaction. If any document fails to be
+        deleted, the transaction is aborted, so none of them are, and the first error is returned. */
+    pub fn delete_documents_atomically(&mut self,
+                                       docs: &[Document],
+                                       concurrency: ConcurrencyControl)
+                                       -> Result<()>
+    {
+        self.batch(docs.len(), true, |db, i| db.delete_document(&docs[i], concurrency))
+            .into_iter().collect()
+    }
+
+    // Calls `op` with each index in `0..count`, within a single transaction, collecting the
+    // results. If `atomic` is true, stops at the first error and aborts the transaction.
+    fn batch<F>(&mut self, count: usize, atomic: bool, mut op: F) -> Vec<Result<()>>
+        where F: FnMut(&mut Database, usize) -> Result<()>
+    {
+        if let Err(err) = self.begin_transaction() {
+            return (0..count).map(|_| Err(err.clone())).collect();
+        }
+        let mut results = Vec::with_capacity(count);
+        let mut failed = false;
+        for i in 0..count {
+            let result = op(self, i);
+            failed = failed || result.is_err();
+            results.push(result);
+            if failed && atomic {
+                break;
+            }
+        }
+        if let Err(err) = self.end_transaction(!(failed && atomic)) {
+            return (0..count).map(|_| Err(err.clone())).collect();
+        }
+        return results;
+    }
+}
+
+
+//////// REVISION IDS:
+
+
+/** A parsed revision ID. Revision IDs come in two forms:
+    - Tree-based, `"<generation>-<digest>"`, e.g. `"3-a1b2c3"`. The generation counts the
+      saves of the document.
+    - Version vectors, `"<timestamp>@<source>"` with optional older versions after a comma or
+      semicolon, e.g. `"17a6f3b5e2c80000@AbCd"`. The timestamp is in hex nanoseconds.
+
+    Revision IDs are ordered the way Couchbase Lite picks a winner between conflicting
+    revisions: by generation, then digest; or by timestamp, then source. Version vectors sort
+    after tree-based revision IDs, since they replace them when a database is upgraded. */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RevisionId {
+    rev_id: String,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum RevisionKey<'a> {
+    Invalid(&'a str),
+    Tree(u64, &'a str),
+    Version(u64, &'a str),
+}
+
+impl RevisionId {
+    /** Wraps a revision ID string. It doesn't have to be valid; an unparseable revision ID
+        just has no generation or timestamp, and sorts before valid ones. */
+    pub fn new(rev_id: &str) -> RevisionId {
+        RevisionId{rev_id: rev_id.to_string()}
+    }
+
+    /** The revision ID as a string. */
+    pub fn as_str(&self) -> &str {
+        &self.rev_id
+    }
+
+    /** Returns true if this is a version vector, rather than a tree-based revision ID. */
+    pub fn is_version_vector(&self) -> bool {
+        match self.key() {
+            RevisionKey::Version(..) => true,
+            _ => false,
+        }
+    }
+
+    /** The generation number of a tree-based revision ID. */
+    pub fn generation(&self) -> Option<u64> {
+        match self.key() {
+            RevisionKey::Tree(generation, _) => Some(generation),
+            _ => None,
+        }
+    }
+
+    /** The time at which a version-vector revision was saved. */
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        match self.key() {
+            RevisionKey::Version(nanos, _) => Some(Timestamp::from_millis((nanos / 1_000_000) as i64)),
+            _ => None,
+        }
+    }
+
+    fn key(&self) -> RevisionKey<'_> {
+        let current = self.rev_id.split(|c| c == ',' || c == ';').next().unwrap_or("").trim();
+        if let Some(at) = current.find('@') {
+            if let Ok(time) = u64::from_str_radix(&current[..at], 16) {
+                return RevisionKey::Version(time, &current[at+1..]);
+            }
+        } else if let Some(dash) = current.find('-') {
+            if let Ok(generation) = current[..dash].parse::<u64>() {
+                if generation > 0 {
+                    return RevisionKey::Tree(generation, &current[dash+1..]);
+                }
+            }
+        }
+        return RevisionKey::Invalid(&self.rev_id);
+    }
+}
+
+impl PartialOrd for RevisionId {
+    fn partial_cmp(&self, other: &RevisionId) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RevisionId {
+    fn cmp(&self, other: &RevisionId) -> Ordering {
+        self.key().cmp(&other.key()).then_with(|| self.rev_id.cmp(&other.rev_id))
+    }
+}
+
+impl fmt::Display for RevisionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.rev_id)
+    }
+}
+
+
 //////// DOCUMENT API:
 
 
@@ -140,13 +378,27 @@ impl Document {
     /** Creates a new, empty document in memory, with an automatically generated unique ID.
         It will not be added to a database until saved. */
     pub fn new() -> Self {
-        unsafe { Document{_ref: CBLDocument_Create()} }
+        unsafe { Document::adopt(CBLDocument_Create(), ptr::null_mut(), 0) }
     }
 
     /** Creates a new, empty document in memory, with the given ID.
         It will not be added to a database until saved. */
     pub fn new_with_id(id: &str) -> Self {
-        unsafe { Document{_ref: CBLDocument_CreateWithID(as_slice(id))} }
+        unsafe { Document::adopt(CBLDocument_CreateWithID(as_slice(id)), ptr::null_mut(), 0) }
+    }
+
+    /** Creates a new, empty document in memory, with an ID from the given generator.
+        It will not be added to a database until saved. */
+    pub fn new_with_generator<G: IdGenerator + ?Sized>(generator: &G) -> Self {
+        Document::new_with_id(&generator.generate_id())
+    }
+
+    /** Wraps a C document, taking over the caller's reference to it. `db` is the database it
+        was read from or saved to, or null; it's retained. */
+    pub(crate) unsafe fn adopt(_ref: *mut CBLDocument,
+                               db: *mut CBLDatabase,
+                               flags: CBLDocumentFlags) -> Document {
+        Document{_ref, _db: retain(db), flags}
     }
 
     /** Deletes a document from the database. (Deletions are replicated, unlike purges.) */
@@ -175,6 +427,44 @@ impl Document {
         }
     }
 
+    /** Returns a document's revision ID in parsed form, which can be compared with others.
+        If the document doesn't exist yet, this method returns None. */
+    pub fn revision(&self) -> Option<RevisionId> {
+        self.revision_id().map(RevisionId::new)
+    }
+
+    /** Returns the generation number of the document's revision, i.e. the number of times it's
+        been saved; or 0 if the document doesn't exist yet, or has a version-vector revision ID. */
+    pub fn generation(&self) -> u64 {
+        self.revision().and_then(|rev| rev.generation()).unwrap_or(0)
+    }
+
+    /** Returns the time the current revision was saved, if the revision ID is a version vector,
+        which encodes it. Tree-based revision IDs don't record a time, so this returns None. */
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.revision().and_then(|rev| rev.timestamp())
+    }
+
+    /** Returns true if this revision is a deletion, according to the revision flags the C API
+        passed along with the document. Only a `Replicator`'s filters get those flags. A
+        document read by `Database::get_document` is never deleted, because reading a deleted
+        document fails with `NotFound`. `Database::delete_document` doesn't change the flags of
+        the in-memory copy it's given. */
+    pub fn is_deleted(&self) -> bool {
+        self.flags & kCBLDocumentFlagsDeleted != 0
+    }
+
+    /** Returns the time, if any, at which the document will expire and be purged. The C API
+        doesn't store the expiration in the document, so it's looked up, when this is called, in
+        the database the document was read from or saved to. A document that's never been saved
+        has no expiration. */
+    pub fn expiration(&self) -> Result<Option<Timestamp>> {
+        if self._db.is_null() {
+            return Ok(None);
+        }
+        unsafe { get_expiration(self._db, self.id()) }
+    }
+
     /** Returns a document's current sequence in the local database.
         This number increases every time the document is saved, and a more recently saved document
         will have a greater sequence number than one saved earlier, so sequences may be used as an
@@ -190,6 +480,18 @@ impl Document {
         unsafe { Dict::wrap(CBLDocument_Properties(self._ref), self) }
     }
 
+    /** Looks up a nested property by key path, e.g. `address.geo[0].lat`.
+        See `Value::at_path`. */
+    pub fn get_path(&self, path: &str) -> Result<Value> {
+        self.properties().get_path(path)
+    }
+
+    /** Returns a JSON delta describing the changes from this document's properties to
+        another's, e.g. an older and newer revision. Apply it with `MutableDict::apply_delta`. */
+    pub fn diff(&self, newer: &Document) -> JsonDelta {
+        self.properties().diff(&newer.properties())
+    }
+
     /** Returns a document's properties as an mutable dictionary. Any changes made to this
         dictionary will be saved to the database when this Document instance is saved. */
     pub fn mutable_properties(&mut self) -> MutableDict {
@@ -220,13 +522,31 @@ impl Document {
 
 impl Drop for Document {
     fn drop(&mut self) {
-        unsafe { release(self._ref); }
+        unsafe {
+            release(self._ref);
+            release(self._db);
+        }
+    }
+}
+
+
+unsafe fn get_expiration(db: *mut CBLDatabase, doc_id: &str) -> Result<Option<Timestamp>> {
+    let mut error = CBLError::default();
+    let exp = CBLDatabase_GetDocumentExpiration(db, as_slice(doc_id), &mut error);
+    if exp < 0 {
+        return failure(error);
+    } else if exp == 0 {
+        return Ok(None);
+    } else {
+        return Ok(Some(Timestamp(exp)));
     }
 }
 
 
 impl Clone for Document {
     fn clone(&self) -> Self {
-        unsafe { Document{_ref: retain(self._ref)} }
+        unsafe {
+            Document{_ref: retain(self._ref), _db: retain(self._db), flags: self.flags}
+        }
     }
 }
@@ -18,6 +18,8 @@
 #![allow(non_upper_case_globals)]
 
 use super::c_api::*;
+use super::query::QueryErrorLocation;
+use super::validation::ValidationError;
 use enum_primitive::FromPrimitive;
 use std::fmt;
 
@@ -26,21 +28,25 @@ use std::fmt;
 
 
 /** Error type. Wraps multiple types of errors in an enum. */
+#[derive(Clone)]
 pub struct Error {
     pub code: ErrorCode,
-    pub(crate) internal_info: Option<u32>
+    pub(crate) internal_info: Option<u32>,
+    pub(crate) query_location: Option<Box<QueryErrorLocation>>   // Where a query's syntax error is
 }
 
 
 /** The enum that stores the error domain and code for an Error. */
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ErrorCode {
     CouchbaseLite   (CouchbaseLiteError),
     POSIX           (i32),
     SQLite          (i32),
     Fleece          (FleeceError),
     Network         (NetworkError),
-    WebSocket       (i32)
+    WebSocket       (i32),
+    Validation      (ValidationError),  // A document failed validation before being saved or pulled
+    Serde           (String)            // Error converting between Fleece and Rust via serde
 }
 
 // Redefine `Result` to assume our `Error` type
@@ -129,15 +135,24 @@ enum_from_primitive! {
 
 impl Error {
     pub(crate) fn new(err: &CBLError) -> Error {
-        Error{code: ErrorCode::new(err), internal_info: Some(err.internal_info)}
+        Error{code: ErrorCode::new(err), internal_info: Some(err.internal_info), query_location: None}
     }
 
     pub(crate) fn cbl_error(e: CouchbaseLiteError) -> Error {
-        Error{code: ErrorCode::CouchbaseLite(e), internal_info: None}
+        Error{code: ErrorCode::CouchbaseLite(e), internal_info: None, query_location: None}
     }
 
     pub(crate) fn fleece_error(e: FLError) -> Error {
-        Error{code: ErrorCode::from_fleece(e), internal_info: None}
+        Error{code: ErrorCode::from_fleece(e), internal_info: None, query_location: None}
+    }
+
+    pub(crate) fn validation_error(e: ValidationError) -> Error {
+        Error{code: ErrorCode::Validation(e), internal_info: None, query_location: None}
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn serde_error(message: String) -> Error {
+        Error{code: ErrorCode::Serde(message), internal_info: None, query_location: None}
     }
 
     pub(crate) fn as_cbl_error(&self) -> CBLError {
@@ -150,16 +165,30 @@ impl Error {
             ErrorCode::POSIX(e) => {domain = kCBLPOSIXDomain; code = *e as i32;},
             ErrorCode::SQLite(e) => {domain = kCBLSQLiteDomain; code = *e as i32;}
             ErrorCode::WebSocket(e) => {domain = kCBLWebSocketDomain; code = *e as i32;}
+            ErrorCode::Validation(_) => {domain = kCBLDomain; code = CouchbaseLiteError::InvalidParameter as i32;}
+            ErrorCode::Serde(_) => {domain = kCBLFleeceDomain; code = FleeceError::EncodeError as i32;}
         }
         return CBLError{domain: domain as CBLErrorDomain, code: code, internal_info: self.internal_info.unwrap_or(0)}
     }
 
+    /** If this error comes from compiling a query with a syntax error, returns where in the
+        query's source the error is. */
+    pub fn query_location(&self) -> Option<&QueryErrorLocation> {
+        self.query_location.as_deref()
+    }
+
     pub fn message(&self) -> String {
         if let ErrorCode::CouchbaseLite(e) = self.code {
             if e == CouchbaseLiteError::UntranslatableError {
                 return "Unknown error".to_string();
             }
         }
+        if let ErrorCode::Validation(ref e) = self.code {
+            return e.to_string();
+        }
+        if let ErrorCode::Serde(ref message) = self.code {
+            return message.clone();
+        }
         unsafe { CBLError_Message(&self.as_cbl_error()).to_string().unwrap() }
     }
 }
@@ -174,7 +203,24 @@ impl fmt::Debug for Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> std::result::Result<(), fmt::Error> {
-        return fmt.write_str(&self.message());
+        fmt.write_str(&self.message())?;
+        if let Some(location) = &self.query_location {
+            fmt.write_fmt(format_args!(", at {}", location))?;
+        }
+        return Ok(());
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        // Unwrap an Error that was wrapped in an io::Error, as by `BlobReader`:
+        if let Some(inner) = err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+            return inner.clone();
+        }
+        match err.raw_os_error() {
+            Some(errno) => Error{code: ErrorCode::POSIX(errno), internal_info: None, query_location: None},
+            None        => Error::cbl_error(CouchbaseLiteError::IOError),
+        }
     }
 }
 
@@ -0,0 +1,242 @@
+// Couchbase Lite query result export
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+use super::base64;
+
+use std::io::Write;
+
+
+/** How blobs in query results are exported. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlobExport {
+    Metadata,       // The blob's metadata dictionary (digest, length, content type)
+    Base64,         // The blob's contents, base64-encoded. Loads each blob into memory.
+}
+
+
+/** Options for exporting a `ResultSet`. */
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub blobs:     BlobExport,
+    pub delimiter: char,        // CSV field delimiter
+    pub header:    bool,        // Write a CSV header line of column names?
+    pub flatten:   bool,        // Flatten nested dicts into CSV columns with dotted names?
+}
+
+impl Default for ExportOptions {
+    fn default() -> ExportOptions {
+        ExportOptions{blobs: BlobExport::Metadata, delimiter: ',', header: true, flatten: true}
+    }
+}
+
+
+impl ResultSet {
+    /** Writes the results as CSV, one line per row, returning the number of rows.
+
+        The columns are named after the query's columns. With `options.flatten`, a column whose
+        value in the first row is a dict is replaced by a column per key, named e.g. `address.city`;
+        since the rows are streamed, keys that only appear in later rows are left out.
+        Arrays, and dicts that aren't flattened, are written as JSON. */
+    pub fn write_csv<W: Write>(mut self, mut writer: W, options: &ExportOptions) -> Result<u64> {
+        let names = self.column_names();
+        let mut columns: Option<Vec<Vec<String>>> = None;     // Key paths of the CSV columns
+        let mut count = 0;
+        let mut line = String::new();
+        while let Some(row) = self.next_row() {
+            let columns = columns.get_or_insert_with(|| {
+                let mut columns = Vec::new();
+                for (i, name) in names.iter().enumerate() {
+                    let path = vec![name.clone()];
+                    if options.flatten {
+                        flattened_paths(path, row.get(i as isize), &mut columns);
+                    } else {
+                        columns.push(path);
+                    }
+                }
+                columns
+            });
+            if count == 0 && options.header {
+                write_csv_header(&mut writer, columns, options)?;
+            }
+
+            line.clear();
+            let dict = row.as_dict();
+            for (i, path) in columns.iter().enumerate() {
+                if i > 0 {
+                    line.push(options.delimiter);
+                }
+                let mut value = dict.get(&path[0]);
+                for key in &path[1..] {
+                    value = value.as_dict().get(key);
+                }
+                write_csv_field(&csv_text(value, options)?, options.delimiter, &mut line);
+            }
+            line.push_str("\r\n");
+            writer.write_all(line.as_bytes())?;
+            count += 1;
+        }
+        if count == 0 && options.header {
+            let columns: Vec<Vec<String>> = names.iter().map(|name| vec![name.clone()]).collect();
+            write_csv_header(&mut writer, &columns, options)?;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /** Writes the results as a JSON array of objects, one per row, whose keys are the column
+        names. Returns the number of rows. */
+    pub fn write_json<W: Write>(self, mut writer: W, options: &ExportOptions) -> Result<u64> {
+        writer.write_all(b"[")?;
+        let count = self.write_json_rows(&mut writer, options, ",\n")?;
+        writer.write_all(if count > 0 {b"\n]\n"} else {b"]\n"})?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /** Writes the results as JSON Lines: a JSON object per row, whose keys are the column names,
+        followed by a newline. Returns the number of rows. */
+    pub fn write_jsonl<W: Write>(self, mut writer: W, options: &ExportOptions) -> Result<u64> {
+        let count = self.write_json_rows(&mut writer, options, "\n")?;
+        if count > 0 {
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    fn write_json_rows<W: Write>(mut self, writer: &mut W, options: &ExportOptions,
+                                 separator: &str) -> Result<u64> {
+        let names = self.column_names();
+        let mut count = 0;
+        let mut json = String::new();
+        while let Some(row) = self.next_row() {
+            json.clear();
+            if count > 0 {
+                json += separator;
+            }
+            json.push('{');
+            for (i, name) in names.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                write_json_string(name, &mut json);
+                json.push(':');
+                write_json_value(row.get(i as isize), options, &mut json)?;
+            }
+            json.push('}');
+            writer.write_all(json.as_bytes())?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+
+// Adds the key paths of a value's nested dicts (but not blobs) to `columns`.
+fn flattened_paths(path: Vec<String>, value: Value, columns: &mut Vec<Vec<String>>) {
+    let dict = value.as_dict();
+    if value.is_type(ValueType::Dict) && !value.is_blob() && dict.count() > 0 {
+        for (key, item) in dict.iter() {
+            let mut item_path = path.clone();
+            item_path.push(key.to_string());
+            flattened_paths(item_path, item, columns);
+        }
+    } else {
+        columns.push(path);
+    }
+}
+
+fn write_csv_header<W: Write>(writer: &mut W, columns: &[Vec<String>], options: &ExportOptions) -> Result<()> {
+    let mut line = String::new();
+    for (i, path) in columns.iter().enumerate() {
+        if i > 0 {
+            line.push(options.delimiter);
+        }
+        write_csv_field(&path.join("."), options.delimiter, &mut line);
+    }
+    line.push_str("\r\n");
+    writer.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+// Appends a CSV field, quoted if necessary (RFC 4180.)
+fn write_csv_field(text: &str, delimiter: char, out: &mut String) {
+    if text.contains(|c| c == delimiter || c == '"' || c == '\n' || c == '\r') {
+        out.push('"');
+        out.push_str(&text.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(text);
+    }
+}
+
+// The text of a CSV field: strings as-is, null or missing values empty, others as JSON.
+fn csv_text(value: Value, options: &ExportOptions) -> Result<String> {
+    match value.get_type() {
+        ValueType::Undefined | ValueType::Null => Ok(String::new()),
+        ValueType::String => Ok(value.as_string().unwrap_or("").to_string()),
+        ValueType::Dict if value.is_blob() && options.blobs == BlobExport::Base64 => {
+            blob_base64(value)
+        }
+        _ => {
+            let mut json = String::new();
+            write_json_value(value, options, &mut json)?;
+            Ok(json)
+        }
+    }
+}
+
+// Appends a value as JSON, encoding blobs as specified by the options.
+fn write_json_value(value: Value, options: &ExportOptions, out: &mut String) -> Result<()> {
+    match value.get_type() {
+        ValueType::Undefined => out.push_str("null"),
+        ValueType::String => write_json_string(value.as_string().unwrap_or(""), out),
+        ValueType::Array => {
+            out.push('[');
+            for (i, item) in value.as_array().iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_value(item, options, out)?;
+            }
+            out.push(']');
+        }
+        ValueType::Dict if value.is_blob() && options.blobs == BlobExport::Base64 => {
+            write_json_string(&blob_base64(value)?, out);
+        }
+        ValueType::Dict => {
+            out.push('{');
+            for (i, (key, item)) in value.as_dict().iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json_string(key, out);
+                out.push(':');
+                write_json_value(item, options, out)?;
+            }
+            out.push('}');
+        }
+        _ => out.push_str(&value.to_json()),
+    }
+    Ok(())
+}
+
+fn blob_base64(value: Value) -> Result<String> {
+    let blob = value.as_blob().ok_or_else(|| Error::cbl_error(CouchbaseLiteError::NotFound))?;
+    Ok(base64::encode(&blob.load_content()?, false))
+}
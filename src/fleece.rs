@@ -27,6 +27,9 @@ use std::mem::MaybeUninit;
 use std::ptr;
 use std::str;
 
+// The serde conversions can also be reached through this module, e.g. `fleece::from_value`.
+#[cfg(feature = "serde")] pub use super::fleece_serde::{from_value, to_mutable_dict};
+
 
 //////// CONTAINER
 
@@ -43,9 +46,13 @@ pub struct Fleece {
 
 impl Fleece {
     pub fn parse(data: &[u8], trust: Trust) -> Result<Self> {
+        Fleece::parse_with(data, trust, ptr::null_mut())
+    }
+
+    pub(crate) fn parse_with(data: &[u8], trust: Trust, keys: FLSharedKeys) -> Result<Self> {
         unsafe {
             let mut copied = FLSlice_Copy(bytes_as_slice(data));
-            let doc = FLDoc_FromResultData(copied, trust as u32, ptr::null_mut(), NULL_SLICE);
+            let doc = FLDoc_FromResultData(copied, trust as u32, keys, NULL_SLICE);
             if doc.is_null() {
                 copied.release();
                 return Err(Error::fleece_error(FLError_kFLInvalidData));
@@ -65,6 +72,13 @@ impl Fleece {
         }
     }
 
+    /** Creates a new Fleece document containing a copy of a value. */
+    pub fn copy_of<V: FleeceReference>(value: &V) -> Result<Self> {
+        let mut encoder = Encoder::new();
+        encoder.write_value(value);
+        encoder.finish()
+    }
+
     pub fn root(&self) -> Value {
         unsafe {
             Value::wrap(FLDoc_GetRoot(self._ref), self)
@@ -102,12 +116,52 @@ impl Clone for Fleece {
     }
 }
 
+// A Fleece document is immutable and atomically ref-counted, so it can be shared between threads.
+unsafe impl Send for Fleece { }
+unsafe impl Sync for Fleece { }
+
+
+//////// JSON DELTA
+
+
+/** A description, in JSON, of the changes between an older and a newer value.
+    Created by `FleeceReference::diff`; applying it with `MutableDict::apply_delta` or
+    `FleeceReference::patched` to the older value produces the newer one.
+    The JSON is compact, so it's suitable for sending to peers that have the older value. */
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct JsonDelta {
+    json: String
+}
+
+impl JsonDelta {
+    /** Wraps a JSON delta, e.g. one received from a peer. */
+    pub fn from_json<S: Into<String>>(json: S) -> JsonDelta {
+        JsonDelta{json: json.into()}
+    }
+
+    /** Returns true if the two values were equal, so there are no changes. */
+    pub fn is_empty(&self) -> bool {
+        self.json.is_empty()
+    }
+
+    /** The delta's JSON form, or an empty string if there are no changes. */
+    pub fn as_json(&self) -> &str {
+        &self.json
+    }
+}
+
+impl fmt::Display for JsonDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.json)
+    }
+}
+
 
 //////// VALUE
 
 
 enum_from_primitive! {
-    #[derive(Debug, PartialEq)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub enum ValueType {
         Undefined = -1,  // Type of a NULL pointer, i.e. no such value, like JSON `undefined`
         Null = 0,        // Equivalent to a JSON 'null'
@@ -141,6 +195,37 @@ pub trait FleeceReference : Default + PartialEq + Eq + std::ops::Not + fmt::Debu
         Blob::from_value(self)
     }
 
+    // JSON deltas:
+
+    /** Returns a JSON delta describing how to change this value into `newer`. */
+    fn diff<V: FleeceReference>(&self, newer: &V) -> JsonDelta {
+        unsafe {
+            let json = FLCreateJSONDelta(self._fleece_ref(), newer._fleece_ref()).to_string();
+            JsonDelta{json: json.unwrap_or_default()}
+        }
+    }
+
+    /** Returns a new Fleece document containing the result of applying a JSON delta to this
+        value. The delta should have been created by calling `diff` on an equal value. */
+    fn patched(&self, delta: &JsonDelta) -> Result<Fleece> {
+        if delta.is_empty() {
+            // No changes, so just make a copy:
+            return Fleece::copy_of(self);
+        }
+        unsafe {
+            let mut error: FLError = 0;
+            let mut data = FLApplyJSONDelta(self._fleece_ref(), as_slice(&delta.json), &mut error);
+            if !data.as_slice() {
+                return Err(Error::fleece_error(error));
+            }
+            let doc = FLDoc_FromResultData(data, Trust::Trusted as u32, ptr::null_mut(), NULL_SLICE);
+            if doc.is_null() {
+                data.release();
+                return Err(Error::fleece_error(FLError_kFLInvalidData));
+            }
+            return Ok(Fleece{_ref: doc});
+        }
+    }
 }
 
 
@@ -204,6 +289,20 @@ impl<'f> Value<'f> {
     pub fn as_dict(&self) -> Dict<'f> {
         unsafe { Dict{_ref: FLValue_AsDict(self._ref), _owner: self._owner} }
     }
+
+    /** Looks up a nested value by key path, e.g. `address.geo[0].lat`. Returns an undefined
+        value if nothing is at that path, or an error if the path isn't valid.
+        To evaluate the same path many times, compile it once as a `KeyPath`. */
+    pub fn at_path(&self, path: &str) -> Result<Value<'f>> {
+        unsafe {
+            let mut error: FLError = 0;
+            let value = FLKeyPath_EvalOnce(as_slice(path), self._ref, &mut error);
+            if value.is_null() && error != 0 {
+                return Err(Error::fleece_error(error));
+            }
+            return Ok(Value{_ref: value, _owner: self._owner});
+        }
+    }
 }
 
 impl<'f> FleeceReference for Value<'f> {
@@ -382,6 +481,11 @@ impl<'f> Dict<'f> {
         unsafe { Value{_ref: FLDict_GetWithKey(self._ref, &mut key._innards), _owner: self._owner} }
     }
 
+    /** Looks up a nested value by key path, e.g. `address.geo[0].lat`. See `Value::at_path`. */
+    pub fn get_path(&self, path: &str) -> Result<Value<'f>> {
+        self.as_value().at_path(path)
+    }
+
     pub fn iter(&self) -> DictIterator<'f> {
         unsafe {
             let mut i = MaybeUninit::<FLDictIterator>::uninit();
@@ -451,6 +555,85 @@ impl DictKey {
 }
 
 
+//////// KEY PATH
+
+
+/** A compiled key path, for quickly looking up a nested value, e.g. `address.geo[0].lat`.
+    Dict keys are separated by `.`, and array indexes are given in brackets. A negative
+    index counts back from the end of the array. */
+pub struct KeyPath {
+    _ref: FLKeyPath
+}
+
+/** One component of a KeyPath. */
+pub(crate) enum KeyPathElement {
+    Key(String),
+    Index(i32),
+}
+
+impl KeyPath {
+    pub fn new(path: &str) -> Result<KeyPath> {
+        unsafe {
+            let mut error: FLError = 0;
+            let p = FLKeyPath_New(as_slice(path), &mut error);
+            if p.is_null() {
+                return Err(Error::fleece_error(error));
+            }
+            return Ok(KeyPath{_ref: p});
+        }
+    }
+
+    /** Evaluates the path starting from `root`, returning the value it points to, or an
+        undefined value if there's nothing there. */
+    pub fn eval<'f>(&self, root: Value<'f>) -> Value<'f> {
+        unsafe { Value{_ref: FLKeyPath_Eval(self._ref, root._ref), _owner: root._owner} }
+    }
+
+    pub(crate) fn elements(&self) -> Vec<KeyPathElement> {
+        let mut elements = Vec::new();
+        unsafe {
+            let mut key = NULL_SLICE;
+            let mut index: i32 = 0;
+            while FLKeyPath_GetElement(self._ref, elements.len() as u64, &mut key, &mut index) {
+                elements.push(match key.to_string() {
+                    Some(k) => KeyPathElement::Key(k),
+                    None    => KeyPathElement::Index(index),
+                });
+            }
+        }
+        return elements;
+    }
+}
+
+impl Drop for KeyPath {
+    fn drop(&mut self) {
+        unsafe { FLKeyPath_Free(self._ref); }
+    }
+}
+
+impl PartialEq for KeyPath {
+    fn eq(&self, other: &Self) -> bool {
+        unsafe { FLKeyPath_Equals(self._ref, other._ref) }
+    }
+}
+
+impl Eq for KeyPath { }
+
+impl fmt::Debug for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("KeyPath")
+         .field(&self.to_string())
+         .finish()
+    }
+}
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        unsafe { f.write_str(&FLKeyPath_ToString(self._ref).to_string().unwrap()) }
+    }
+}
+
+
 //////// DICT ITERATOR
 
 
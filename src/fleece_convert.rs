@@ -0,0 +1,369 @@
+// Conversions between Fleece values and Rust types
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+use super::c_api::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::Hash;
+
+
+//////// PROPERTY ERROR:
+
+
+/** The error returned when a Fleece value can't be converted to a Rust type.
+    The key path locates the value relative to where the conversion started, so a bad
+    element of an array property looks like `tags[3]`. */
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyError {
+    /** There is no value at the key path. */
+    Missing     {key_path: String},
+    /** The value has a different type than was asked for. */
+    WrongType   {key_path: String, expected: &'static str, actual: ValueType},
+    /** The value is a number that doesn't fit in the integer type asked for. */
+    OutOfRange  {key_path: String, expected: &'static str},
+}
+
+type ConversionResult<T> = std::result::Result<T, PropertyError>;
+
+impl PropertyError {
+    /** The key path of the value that couldn't be converted. */
+    pub fn key_path(&self) -> &str {
+        match self {
+            PropertyError::Missing{key_path}         => key_path,
+            PropertyError::WrongType{key_path, ..}   => key_path,
+            PropertyError::OutOfRange{key_path, ..}  => key_path,
+        }
+    }
+
+    /** Returns an error for a value that isn't of the expected type. */
+    pub fn wrong_type(value: Value, expected: &'static str) -> PropertyError {
+        if !value {
+            PropertyError::Missing{key_path: String::new()}
+        } else {
+            PropertyError::WrongType{key_path: String::new(), expected, actual: value.get_type()}
+        }
+    }
+
+    // Prefixes the error's key path with the key or `[index]` of the containing value.
+    pub(crate) fn within(mut self, parent: &str) -> PropertyError {
+        let path = match self {
+            PropertyError::Missing{ref mut key_path}          => key_path,
+            PropertyError::WrongType{ref mut key_path, ..}    => key_path,
+            PropertyError::OutOfRange{ref mut key_path, ..}   => key_path,
+        };
+        *path = if path.is_empty() {
+            parent.to_string()
+        } else if path.starts_with('[') {
+            format!("{}{}", parent, path)
+        } else {
+            format!("{}.{}", parent, path)
+        };
+        return self;
+    }
+}
+
+impl fmt::Display for PropertyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PropertyError::Missing{key_path} =>
+                write!(f, "property `{}` is missing", key_path),
+            PropertyError::WrongType{key_path, expected, actual} =>
+                write!(f, "property `{}` should be {} but is {:?}", key_path, expected, actual),
+            PropertyError::OutOfRange{key_path, expected} =>
+                write!(f, "property `{}` is out of range for {}", key_path, expected),
+        }
+    }
+}
+
+impl std::error::Error for PropertyError { }
+
+
+//////// FROM FLEECE:
+
+
+/** A type that can be created from a Fleece value. */
+pub trait FromFleece<'f> : Sized {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<Self>;
+}
+
+impl<'f> Dict<'f> {
+    /** Returns the value for a key, converted to a Rust type. The error tells whether the key
+        was missing, or its value was of the wrong type. */
+    pub fn get_as<T: FromFleece<'f>>(&self, key: &str) -> ConversionResult<T> {
+        T::from_fleece(self.get(key)).map_err(|e| e.within(key))
+    }
+}
+
+impl MutableDict {
+    /** Returns the value for a key, converted to a Rust type. See `Dict::get_as`. */
+    pub fn get_as<'a, T: FromFleece<'a>>(&'a self, key: &str) -> ConversionResult<T> {
+        self.as_dict().get_as(key)
+    }
+}
+
+impl<'r> Row<'r> {
+    /** Returns the value of a column, converted to a Rust type. See `Dict::get_as`. */
+    pub fn get_as<T: FromFleece<'r>>(&self, index: isize) -> ConversionResult<T> {
+        T::from_fleece(self.get(index)).map_err(|e| match self.column_name(index) {
+            Some(name) => e.within(name),
+            None       => e.within(&format!("${}", index + 1)),
+        })
+    }
+}
+
+macro_rules! from_fleece_integer {
+    ($($t:ty),*) => {$(
+        impl<'f> FromFleece<'f> for $t {
+            fn from_fleece(value: Value<'f>) -> ConversionResult<$t> {
+                if !value.is_integer() {
+                    return Err(PropertyError::wrong_type(value, stringify!($t)));
+                }
+                let n = if unsafe { FLValue_IsUnsigned(value._ref) } {
+                    <$t>::try_from(value.as_u64_or_0()).ok()
+                } else {
+                    <$t>::try_from(value.as_i64_or_0()).ok()
+                };
+                n.ok_or_else(|| PropertyError::OutOfRange{key_path: String::new(),
+                                                          expected: stringify!($t)})
+            }
+        }
+    )*}
+}
+
+from_fleece_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl<'f> FromFleece<'f> for f64 {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<f64> {
+        value.as_f64().ok_or_else(|| PropertyError::wrong_type(value, "f64"))
+    }
+}
+
+impl<'f> FromFleece<'f> for f32 {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<f32> {
+        value.as_f32().ok_or_else(|| PropertyError::wrong_type(value, "f32"))
+    }
+}
+
+impl<'f> FromFleece<'f> for bool {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<bool> {
+        value.as_bool().ok_or_else(|| PropertyError::wrong_type(value, "bool"))
+    }
+}
+
+impl<'f> FromFleece<'f> for &'f str {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<&'f str> {
+        value.as_string().ok_or_else(|| PropertyError::wrong_type(value, "string"))
+    }
+}
+
+impl<'f> FromFleece<'f> for String {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<String> {
+        <&str>::from_fleece(value).map(|s| s.to_string())
+    }
+}
+
+impl<'f> FromFleece<'f> for Timestamp {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<Timestamp> {
+        value.as_timestamp().ok_or_else(|| PropertyError::wrong_type(value, "timestamp"))
+    }
+}
+
+impl<'f> FromFleece<'f> for Blob {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<Blob> {
+        value.as_blob().ok_or_else(|| PropertyError::wrong_type(value, "blob"))
+    }
+}
+
+impl<'f> FromFleece<'f> for Value<'f> {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<Value<'f>> {
+        if !value {
+            return Err(PropertyError::wrong_type(value, "value"));
+        }
+        return Ok(value);
+    }
+}
+
+impl<'f> FromFleece<'f> for Array<'f> {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<Array<'f>> {
+        if !value.is_type(ValueType::Array) {
+            return Err(PropertyError::wrong_type(value, "array"));
+        }
+        return Ok(value.as_array());
+    }
+}
+
+impl<'f> FromFleece<'f> for Dict<'f> {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<Dict<'f>> {
+        if !value.is_type(ValueType::Dict) {
+            return Err(PropertyError::wrong_type(value, "dict"));
+        }
+        return Ok(value.as_dict());
+    }
+}
+
+/** A missing or null value converts to None. */
+impl<'f, T: FromFleece<'f>> FromFleece<'f> for Option<T> {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<Option<T>> {
+        if !value || value.is_type(ValueType::Null) {
+            return Ok(None);
+        }
+        return T::from_fleece(value).map(Some);
+    }
+}
+
+impl<'f, T: FromFleece<'f>> FromFleece<'f> for Vec<T> {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<Vec<T>> {
+        let array = Array::from_fleece(value)?;
+        array.iter().enumerate()
+             .map(|(i, item)| T::from_fleece(item).map_err(|e| e.within(&format!("[{}]", i))))
+             .collect()
+    }
+}
+
+impl<'f, T: FromFleece<'f>> FromFleece<'f> for HashMap<String, T> {
+    fn from_fleece(value: Value<'f>) -> ConversionResult<HashMap<String, T>> {
+        let dict = Dict::from_fleece(value)?;
+        dict.iter()
+            .map(|(key, item)| T::from_fleece(item).map(|v| (key.to_string(), v))
+                                                     .map_err(|e| e.within(key)))
+            .collect()
+    }
+}
+
+
+//////// TO FLEECE:
+
+
+/** A type that can be stored in a Fleece collection, via `Slot::put`. */
+pub trait ToFleece {
+    /** Stores this value in the slot. */
+    fn put_into(&self, slot: Slot);
+}
+
+impl<'s> Slot<'s> {
+    /** Stores any value that implements `ToFleece`. */
+    pub fn put<T: ToFleece>(self, value: T) {
+        value.put_into(self)
+    }
+}
+
+impl<'a, T: ToFleece + ?Sized> ToFleece for &'a T {
+    fn put_into(&self, slot: Slot) { (**self).put_into(slot) }
+}
+
+macro_rules! to_fleece_signed {
+    ($($t:ty),*) => {$(
+        impl ToFleece for $t {
+            fn put_into(&self, slot: Slot) { unsafe { FLSlot_SetInt(slot._ref, *self as i64) } }
+        }
+    )*}
+}
+
+macro_rules! to_fleece_unsigned {
+    ($($t:ty),*) => {$(
+        impl ToFleece for $t {
+            fn put_into(&self, slot: Slot) { unsafe { FLSlot_SetUInt(slot._ref, *self as u64) } }
+        }
+    )*}
+}
+
+to_fleece_signed!(i8, i16, i32, i64, isize);
+to_fleece_unsigned!(u8, u16, u32, u64, usize);
+
+impl ToFleece for f32 {
+    fn put_into(&self, slot: Slot) { unsafe { FLSlot_SetFloat(slot._ref, *self) } }
+}
+
+impl ToFleece for f64 {
+    fn put_into(&self, slot: Slot) { slot.put_f64(*self) }
+}
+
+impl ToFleece for bool {
+    fn put_into(&self, slot: Slot) { slot.put_bool(*self) }
+}
+
+impl ToFleece for str {
+    fn put_into(&self, slot: Slot) { slot.put_string(self) }
+}
+
+impl ToFleece for String {
+    fn put_into(&self, slot: Slot) { slot.put_string(self) }
+}
+
+/** Timestamps are stored as milliseconds since the Unix epoch. */
+impl ToFleece for Timestamp {
+    fn put_into(&self, slot: Slot) { slot.put_i64(self.millis()) }
+}
+
+impl ToFleece for Blob {
+    fn put_into(&self, slot: Slot) { unsafe { FLSlot_SetBlob(slot._ref, self._ref as *mut CBLBlob) } }
+}
+
+/** None is stored as a null. */
+impl<T: ToFleece> ToFleece for Option<T> {
+    fn put_into(&self, slot: Slot) {
+        match self {
+            Some(value) => value.put_into(slot),
+            None        => slot.put_null(),
+        }
+    }
+}
+
+impl<T: ToFleece> ToFleece for [T] {
+    fn put_into(&self, slot: Slot) {
+        let mut array = MutableArray::new();
+        for item in self {
+            item.put_into(array.append());
+        }
+        slot.put_value(&array);
+    }
+}
+
+impl<T: ToFleece> ToFleece for Vec<T> {
+    fn put_into(&self, slot: Slot) { self.as_slice().put_into(slot) }
+}
+
+fn put_map<'a, K, T, I>(entries: I, slot: Slot)
+    where K: AsRef<str> + 'a, T: ToFleece + 'a, I: Iterator<Item=(&'a K, &'a T)>
+{
+    let mut dict = MutableDict::new();
+    for (key, value) in entries {
+        value.put_into(dict.at(key.as_ref()));
+    }
+    slot.put_value(&dict);
+}
+
+impl<K: AsRef<str> + Eq + Hash, T: ToFleece> ToFleece for HashMap<K, T> {
+    fn put_into(&self, slot: Slot) { put_map(self.iter(), slot) }
+}
+
+impl<K: AsRef<str> + Ord, T: ToFleece> ToFleece for BTreeMap<K, T> {
+    fn put_into(&self, slot: Slot) { put_map(self.iter(), slot) }
+}
+
+macro_rules! to_fleece_reference {
+    ($($t:ty),*) => {$(
+        impl ToFleece for $t {
+            fn put_into(&self, slot: Slot) { slot.put_value(self) }
+        }
+    )*}
+}
+
+to_fleece_reference!(Value<'_>, Array<'_>, Dict<'_>, MutableArray, MutableDict);
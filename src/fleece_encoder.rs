@@ -0,0 +1,267 @@
+// Couchbase Lite Fleece encoder API
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+use super::slice::*;
+use super::c_api::*;
+
+use std::str;
+
+
+//////// ENCODER:
+
+
+/** The output format of an `Encoder`. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderFormat {
+    Fleece,
+    JSON,
+    JSON5,      // JSON5: unquoted dict keys where possible
+}
+
+/** Writes Fleece (or JSON) data a value at a time, without building mutable collections.
+    Collections are written by calling `begin_array` or `begin_dict`, then writing each item
+    (preceded by `write_key` in a dict), then `end_array` or `end_dict`:
+    ```ignore
+    let mut enc = Encoder::new();
+    enc.begin_dict(2);
+    enc.write_key("name").write_string("Widget");
+    enc.write_key("sizes").begin_array(2).write_i64(3).write_i64(5).end_array();
+    enc.end_dict();
+    let doc = enc.finish()?;
+    ```
+    Errors, such as a dict value without a key, are remembered by the encoder and returned by
+    `finish`; later writes are ignored. */
+pub struct Encoder {
+    pub(crate) _ref: FLEncoder,
+    format: EncoderFormat,
+}
+
+impl Encoder {
+    /** Creates an encoder that writes Fleece. */
+    pub fn new() -> Encoder {
+        Encoder::with_format(EncoderFormat::Fleece)
+    }
+
+    /** Creates an encoder that writes the given format. */
+    pub fn with_format(format: EncoderFormat) -> Encoder {
+        let format_code = match format {
+            EncoderFormat::Fleece => FLEncoderFormat_kFLEncodeFleece,
+            EncoderFormat::JSON   => FLEncoderFormat_kFLEncodeJSON,
+            EncoderFormat::JSON5  => FLEncoderFormat_kFLEncodeJSON5,
+        };
+        unsafe { Encoder{_ref: FLEncoder_NewWithOptions(format_code, 0, true), format} }
+    }
+
+    pub fn format(&self) -> EncoderFormat {
+        self.format
+    }
+
+    /** Encodes dict keys as small integers, using (and adding to) a `SharedKeys` table.
+        This makes the output smaller, but it can only be read with the same shared keys.
+        Only applies to the Fleece format. */
+    pub fn set_shared_keys(&mut self, keys: &SharedKeys) {
+        unsafe { FLEncoder_SetSharedKeys(self._ref, keys._ref) }
+    }
+
+    /** Discards everything written so far, and any error, so the encoder can be reused. */
+    pub fn reset(&mut self) {
+        unsafe { FLEncoder_Reset(self._ref) }
+    }
+
+    pub fn write_null(&mut self) -> &mut Self {
+        unsafe { FLEncoder_WriteNull(self._ref); }
+        self
+    }
+
+    pub fn write_bool(&mut self, value: bool) -> &mut Self {
+        unsafe { FLEncoder_WriteBool(self._ref, value); }
+        self
+    }
+
+    pub fn write_i64(&mut self, value: i64) -> &mut Self {
+        unsafe { FLEncoder_WriteInt(self._ref, value); }
+        self
+    }
+
+    pub fn write_u64(&mut self, value: u64) -> &mut Self {
+        unsafe { FLEncoder_WriteUInt(self._ref, value); }
+        self
+    }
+
+    pub fn write_f32(&mut self, value: f32) -> &mut Self {
+        unsafe { FLEncoder_WriteFloat(self._ref, value); }
+        self
+    }
+
+    pub fn write_f64(&mut self, value: f64) -> &mut Self {
+        unsafe { FLEncoder_WriteDouble(self._ref, value); }
+        self
+    }
+
+    pub fn write_string(&mut self, value: &str) -> &mut Self {
+        unsafe { FLEncoder_WriteString(self._ref, as_slice(value)); }
+        self
+    }
+
+    /** Writes binary data. (In JSON, data is written as a base64-encoded string.) */
+    pub fn write_data(&mut self, value: &[u8]) -> &mut Self {
+        unsafe { FLEncoder_WriteData(self._ref, bytes_as_slice(value)); }
+        self
+    }
+
+    /** Writes a copy of an existing Fleece value, including any nested collections. */
+    pub fn write_value<V: FleeceReference>(&mut self, value: &V) -> &mut Self {
+        unsafe { FLEncoder_WriteValue(self._ref, value._fleece_ref()); }
+        self
+    }
+
+    /** Begins an array; the next values written are its items, until `end_array`.
+        `reserve` is the expected number of items, or 0 if unknown. */
+    pub fn begin_array(&mut self, reserve: usize) -> &mut Self {
+        unsafe { FLEncoder_BeginArray(self._ref, reserve as size_t); }
+        self
+    }
+
+    pub fn end_array(&mut self) -> &mut Self {
+        unsafe { FLEncoder_EndArray(self._ref); }
+        self
+    }
+
+    /** Begins a dict; until `end_dict`, each value written must be preceded by `write_key`.
+        `reserve` is the expected number of keys, or 0 if unknown. */
+    pub fn begin_dict(&mut self, reserve: usize) -> &mut Self {
+        unsafe { FLEncoder_BeginDict(self._ref, reserve as size_t); }
+        self
+    }
+
+    pub fn write_key(&mut self, key: &str) -> &mut Self {
+        unsafe { FLEncoder_WriteKey(self._ref, as_slice(key)); }
+        self
+    }
+
+    pub fn end_dict(&mut self) -> &mut Self {
+        unsafe { FLEncoder_EndDict(self._ref); }
+        self
+    }
+
+    /** Returns the error, if any, that has occurred while encoding. */
+    pub fn error(&self) -> Option<Error> {
+        match unsafe { FLEncoder_GetError(self._ref) } {
+            0     => None,
+            error => Some(Error::fleece_error(error)),
+        }
+    }
+
+    /** Finishes encoding, returning the result as a Fleece document. If the format is JSON,
+        the JSON is parsed into Fleece. */
+    pub fn finish(self) -> Result<Fleece> {
+        if self.format == EncoderFormat::Fleece {
+            unsafe {
+                let mut error: FLError = 0;
+                let doc = FLEncoder_FinishDoc(self._ref, &mut error);
+                if doc.is_null() {Err(Error::fleece_error(error))} else {Ok(Fleece{_ref: doc})}
+            }
+        } else {
+            let json = self.finish_data()?;
+            Fleece::parse_json(str::from_utf8(&json).map_err(|_| Error::fleece_error(FLError_kFLJSONError))?)
+        }
+    }
+
+    /** Finishes encoding, returning the encoded data: Fleece, or JSON text. */
+    pub fn finish_data(self) -> Result<Vec<u8>> {
+        unsafe {
+            let mut error: FLError = 0;
+            let data = FLEncoder_Finish(self._ref, &mut error);
+            data.to_vec().ok_or_else(|| Error::fleece_error(error))
+        }
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe { FLEncoder_Free(self._ref) }
+    }
+}
+
+
+//////// SHARED KEYS:
+
+
+/** A table mapping dict keys to small integers, which makes encoded Fleece smaller. Data
+    encoded with shared keys can only be read with the same keys; to read it in another process,
+    send the keys' `state_data` along with it and load it with `load_state_data`. */
+pub struct SharedKeys {
+    pub(crate) _ref: FLSharedKeys,
+}
+
+impl SharedKeys {
+    pub fn new() -> SharedKeys {
+        unsafe { SharedKeys{_ref: FLSharedKeys_New()} }
+    }
+
+    /** The number of keys in the table. */
+    pub fn count(&self) -> usize {
+        unsafe { FLSharedKeys_Count(self._ref) as usize }
+    }
+
+    /** Returns the state of the table, which can be loaded into another `SharedKeys`. */
+    pub fn state_data(&self) -> Vec<u8> {
+        unsafe { FLSharedKeys_GetStateData(self._ref).to_vec().unwrap_or_default() }
+    }
+
+    /** Loads state data from another table. Keys already in this table must match it. */
+    pub fn load_state_data(&mut self, data: &[u8]) -> Result<()> {
+        if unsafe { FLSharedKeys_LoadStateData(self._ref, bytes_as_slice(data)) } {
+            Ok(())
+        } else {
+            Err(Error::fleece_error(FLError_kFLInvalidData))
+        }
+    }
+}
+
+impl Default for SharedKeys {
+    fn default() -> SharedKeys {
+        SharedKeys::new()
+    }
+}
+
+impl Clone for SharedKeys {
+    fn clone(&self) -> Self {
+        unsafe { SharedKeys{_ref: FLSharedKeys_Retain(self._ref)} }
+    }
+}
+
+impl Drop for SharedKeys {
+    fn drop(&mut self) {
+        unsafe { FLSharedKeys_Release(self._ref) }
+    }
+}
+
+
+impl Fleece {
+    /** Like `parse`, for data encoded with shared keys. */
+    pub fn parse_with_shared_keys(data: &[u8], trust: Trust, keys: &SharedKeys) -> Result<Self> {
+        Fleece::parse_with(data, trust, keys._ref)
+    }
+}
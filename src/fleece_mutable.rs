@@ -20,6 +20,7 @@ use super::slice::*;
 use super::c_api::*;
 use super::fleece::*;
 
+use enum_primitive::FromPrimitive;
 use std::fmt;
 use std::marker::PhantomData;
 use std::ptr;
@@ -193,6 +194,26 @@ impl MutableDict {
         unsafe { Slot{_ref: FLMutableDict_Set(self._ref, as_slice(key)), _owner: PhantomData} }
     }
 
+    /** Returns a slot for storing a value at a key path, e.g. `address.geo[0].lat`.
+        Missing intermediate dictionaries and arrays are created, and arrays that are too short
+        are padded with nulls. It's an error if the path runs into an existing value of the
+        wrong type. */
+    pub fn at_path<'s>(&'s mut self, path: &str) -> Result<Slot<'s>> {
+        let elements = KeyPath::new(path)?.elements();
+        unsafe {
+            let mut container = PathContainer::Dict(self._ref);
+            for (i, element) in elements.iter().enumerate() {
+                let location = container.locate(element)?;
+                match elements.get(i + 1) {
+                    None => return Ok(Slot{_ref: location.slot(), _owner: PhantomData}),
+                    Some(KeyPathElement::Key(_))   => container = location.child(false)?,
+                    Some(KeyPathElement::Index(_)) => container = location.child(true)?,
+                }
+            }
+        }
+        return Err(Error::fleece_error(FLError_kFLInvalidData));   // empty path
+    }
+
     pub fn remove(&mut self, key: &str) {
         unsafe { FLMutableDict_Remove(self._ref, as_slice(key)) }
     }
@@ -200,6 +221,25 @@ impl MutableDict {
     pub fn remove_all(&mut self) {
         unsafe { FLMutableDict_RemoveAll(self._ref) }
     }
+
+    /** Applies a JSON delta, created by `diff`, changing this dictionary's contents to the
+        newer version. The delta must have been created relative to a dictionary equal to this
+        one, or the result is unpredictable. */
+    pub fn apply_delta(&mut self, delta: &JsonDelta) -> Result<()> {
+        if delta.is_empty() {
+            return Ok(());
+        }
+        let patched = self.patched(delta)?;
+        let dict = patched.as_dict();
+        if !dict {
+            return Err(Error::fleece_error(FLError_kFLInvalidData));
+        }
+        self.remove_all();
+        for (key, value) in dict {
+            self.at(key).put_value(&value);
+        }
+        return Ok(());
+    }
 }
 
 // "Inherited" API:
@@ -209,6 +249,7 @@ impl MutableDict {
     pub fn empty(&self) -> bool                         { self.as_dict().empty() }
     pub fn get(&self, key: &str) -> Value               { self.as_dict().get(key) }
     pub fn get_key(&self, key: &mut DictKey) -> Value   { self.as_dict().get_key(key) }
+    pub fn get_path(&self, path: &str) -> Result<Value> { self.as_dict().get_path(path) }
     pub fn iter(&self) -> DictIterator                  { self.as_dict().iter() }
 }
 
@@ -279,6 +320,82 @@ impl<'d> Dict<'d> {
 }
 
 
+//////// KEY PATH SUPPORT (INTERNAL):
+
+
+// A mutable collection reached while walking a key path in `MutableDict::at_path`.
+#[derive(Clone, Copy)]
+enum PathContainer {
+    Dict(FLMutableDict),
+    Array(FLMutableArray),
+}
+
+// The place within a PathContainer that a key path element refers to.
+#[derive(Clone, Copy)]
+enum PathLocation<'k> {
+    Key(FLMutableDict, &'k str),
+    Index(FLMutableArray, u32),
+}
+
+impl PathContainer {
+    unsafe fn locate<'k>(self, element: &'k KeyPathElement) -> Result<PathLocation<'k>> {
+        match (self, element) {
+            (PathContainer::Dict(dict), KeyPathElement::Key(key)) => {
+                Ok(PathLocation::Key(dict, key))
+            }
+            (PathContainer::Array(array), KeyPathElement::Index(index)) => {
+                let count = FLArray_Count(array as FLArray) as i64;
+                let mut i = *index as i64;
+                if i < 0 {
+                    i += count;
+                    if i < 0 {
+                        return Err(Error::fleece_error(FLError_kFLOutOfRange));
+                    }
+                } else if i >= count {
+                    FLMutableArray_Resize(array, (i + 1) as u32);
+                }
+                Ok(PathLocation::Index(array, i as u32))
+            }
+            _ => Err(Error::fleece_error(FLError_kFLInvalidData)),
+        }
+    }
+}
+
+impl<'k> PathLocation<'k> {
+    unsafe fn slot(&self) -> FLSlot {
+        match *self {
+            PathLocation::Key(dict, key)      => FLMutableDict_Set(dict, as_slice(key)),
+            PathLocation::Index(array, index) => FLMutableArray_Set(array, index),
+        }
+    }
+
+    // Returns the mutable array or dict at this location, creating it if it's missing or null.
+    unsafe fn child(&self, array: bool) -> Result<PathContainer> {
+        let existing = match *self {
+            PathLocation::Key(dict, key)  => FLDict_Get(dict as FLDict, as_slice(key)),
+            PathLocation::Index(a, index) => FLArray_Get(a as FLArray, index),
+        };
+        let wanted = if array { ValueType::Array } else { ValueType::Dict };
+        match ValueType::from_i32(FLValue_GetType(existing)) {
+            Some(ValueType::Undefined) | Some(ValueType::Null) => {
+                let new_child = if array { FLMutableArray_New() as FLValue }
+                                else     { FLMutableDict_New() as FLValue };
+                FLSlot_SetValue(self.slot(), new_child);
+                FLValue_Release(new_child);
+            }
+            Some(ref t) if *t == wanted => { }
+            _ => return Err(Error::fleece_error(FLError_kFLInvalidData)),
+        }
+        return Ok(match (*self, array) {
+            (PathLocation::Key(d, key), true)    => PathContainer::Array(FLMutableDict_GetMutableArray(d, as_slice(key))),
+            (PathLocation::Key(d, key), false)   => PathContainer::Dict(FLMutableDict_GetMutableDict(d, as_slice(key))),
+            (PathLocation::Index(a, index), true)  => PathContainer::Array(FLMutableArray_GetMutableArray(a, index)),
+            (PathLocation::Index(a, index), false) => PathContainer::Dict(FLMutableArray_GetMutableDict(a, index)),
+        });
+    }
+}
+
+
 //////// SLOT:
 
 
@@ -307,7 +424,7 @@ impl<'s> Slot<'s> {
     }
 
     pub fn put_data<DATA: AsRef<[u8]>>(self, value: DATA) {
-        unsafe { FLSlot_SetString(self._ref, bytes_as_slice(value.as_ref())) }
+        unsafe { FLSlot_SetData(self._ref, bytes_as_slice(value.as_ref())) }
     }
 
     pub fn put_value<VALUE: FleeceReference>(self, value: &VALUE)  {
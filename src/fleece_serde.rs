@@ -0,0 +1,816 @@
+// Serde support for Fleece
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+use super::slice::*;
+use super::c_api::*;
+
+use serde::de::{self, Deserialize, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::sync::mpsc;
+
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::serde_error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Error {
+        Error::serde_error(msg.to_string())
+    }
+}
+
+
+//////// API:
+
+
+/** Serializes a value, which must serialize as a struct or map, to a `MutableDict`. */
+pub fn to_mutable_dict<T: Serialize + ?Sized>(value: &T) -> Result<MutableDict> {
+    let mut holder = MutableArray::new();
+    value.serialize(SlotSerializer{slot: holder.append()})?;
+    unsafe {
+        let dict = FLMutableArray_GetMutableDict(holder._ref, 0);
+        if dict.is_null() {
+            return Err(Error::serde_error("value must serialize as a struct or map".to_string()));
+        }
+        return Ok(MutableDict::adopt(dict));
+    }
+}
+
+impl<'s> Slot<'s> {
+    /** Stores any value that implements `Serialize`. */
+    pub fn put_serializable<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(SlotSerializer{slot: self})
+    }
+}
+
+impl Fleece {
+    /** Serializes a value to a new Fleece document. This encodes it directly, so it's faster
+        than building a `MutableDict` with `to_mutable_dict`. */
+    pub fn from_serializable<T: Serialize + ?Sized>(value: &T) -> Result<Fleece> {
+        let mut encoder = Encoder::new();
+        encoder.write_serializable(value)?;
+        encoder.finish()
+    }
+}
+
+impl Encoder {
+    /** Writes any value that implements `Serialize`. */
+    pub fn write_serializable<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<&mut Self> {
+        value.serialize(EncoderSerializer{encoder: self._ref})?;
+        Ok(self)
+    }
+}
+
+/** Deserializes a Rust value from a Fleece value. Strings and data can be borrowed from
+    the Fleece value. */
+pub fn from_value<'f, T: Deserialize<'f>>(value: Value<'f>) -> Result<T> {
+    T::deserialize(ValueDeserializer{value})
+}
+
+impl Query {
+    /** Sets the query's parameters from the fields of a struct, or entries of a map. */
+    pub fn set_parameters_from<T: Serialize + ?Sized>(&self, parameters: &T) -> Result<()> {
+        self.set_parameters(to_mutable_dict(parameters)?);
+        Ok(())
+    }
+}
+
+
+//////// SERIALIZER:
+
+
+// Serializes a value into a Slot. Enums are represented like serde_json does: a unit variant
+// is a string, and other variants are a dict whose single key is the variant name.
+struct SlotSerializer<'s> {
+    slot: Slot<'s>,
+}
+
+impl<'s> ser::Serializer for SlotSerializer<'s> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = SeqSerializer<'s>;
+    type SerializeTuple = SeqSerializer<'s>;
+    type SerializeTupleStruct = SeqSerializer<'s>;
+    type SerializeTupleVariant = VariantSerializer<'s, SeqSerializer<'s>>;
+    type SerializeMap = MapSerializer<'s>;
+    type SerializeStruct = MapSerializer<'s>;
+    type SerializeStructVariant = VariantSerializer<'s, MapSerializer<'s>>;
+
+    fn serialize_bool(self, v: bool) -> Result<()>  { self.slot.put_bool(v); Ok(()) }
+    fn serialize_i8(self, v: i8) -> Result<()>      { self.slot.put_i64(v); Ok(()) }
+    fn serialize_i16(self, v: i16) -> Result<()>    { self.slot.put_i64(v); Ok(()) }
+    fn serialize_i32(self, v: i32) -> Result<()>    { self.slot.put_i64(v); Ok(()) }
+    fn serialize_i64(self, v: i64) -> Result<()>    { self.slot.put_i64(v); Ok(()) }
+    fn serialize_u8(self, v: u8) -> Result<()>      { self.slot.put_i64(v); Ok(()) }
+    fn serialize_u16(self, v: u16) -> Result<()>    { self.slot.put_i64(v); Ok(()) }
+    fn serialize_u32(self, v: u32) -> Result<()>    { self.slot.put_i64(v); Ok(()) }
+    fn serialize_u64(self, v: u64) -> Result<()>    { self.slot.put(v); Ok(()) }
+    fn serialize_f32(self, v: f32) -> Result<()>    { self.slot.put(v); Ok(()) }
+    fn serialize_f64(self, v: f64) -> Result<()>    { self.slot.put_f64(v); Ok(()) }
+    fn serialize_char(self, v: char) -> Result<()>  { self.slot.put_string(v.to_string()); Ok(()) }
+    fn serialize_str(self, v: &str) -> Result<()>   { self.slot.put_string(v); Ok(()) }
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> { self.slot.put_data(v); Ok(()) }
+    fn serialize_none(self) -> Result<()>           { self.slot.put_null(); Ok(()) }
+    fn serialize_unit(self) -> Result<()>           { self.slot.put_null(); Ok(()) }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32,
+                              variant: &'static str) -> Result<()> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str,
+                                                       value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32,
+                                                        variant: &'static str,
+                                                        value: &T) -> Result<()> {
+        let mut dict = MutableDict::new();
+        value.serialize(SlotSerializer{slot: dict.at(variant)})?;
+        self.slot.put_value(&dict);
+        Ok(())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SeqSerializer<'s>> {
+        Ok(SeqSerializer{slot: Some(self.slot), array: MutableArray::new()})
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer<'s>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer<'s>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str,
+                               _len: usize) -> Result<VariantSerializer<'s, SeqSerializer<'s>>> {
+        Ok(VariantSerializer{slot: self.slot, variant,
+                             inner: SeqSerializer{slot: None, array: MutableArray::new()}})
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<MapSerializer<'s>> {
+        Ok(MapSerializer{slot: Some(self.slot), dict: MutableDict::new(), key: None})
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapSerializer<'s>> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str,
+                                _len: usize) -> Result<VariantSerializer<'s, MapSerializer<'s>>> {
+        Ok(VariantSerializer{slot: self.slot, variant,
+                             inner: MapSerializer{slot: None, dict: MutableDict::new(), key: None}})
+    }
+}
+
+
+// Serializes a sequence into a MutableArray, which is stored in the slot (if any) at the end.
+struct SeqSerializer<'s> {
+    slot:  Option<Slot<'s>>,
+    array: MutableArray,
+}
+
+impl<'s> SeqSerializer<'s> {
+    fn add<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(SlotSerializer{slot: self.array.append()})
+    }
+
+    fn finish(self) -> Result<()> {
+        if let Some(slot) = self.slot {
+            slot.put_value(&self.array);
+        }
+        Ok(())
+    }
+}
+
+impl<'s> ser::SerializeSeq for SeqSerializer<'s> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.add(value) }
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+impl<'s> ser::SerializeTuple for SeqSerializer<'s> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.add(value) }
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+impl<'s> ser::SerializeTupleStruct for SeqSerializer<'s> {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.add(value) }
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+
+// Serializes a map or struct into a MutableDict, which is stored in the slot (if any) at the end.
+struct MapSerializer<'s> {
+    slot: Option<Slot<'s>>,
+    dict: MutableDict,
+    key:  Option<String>,
+}
+
+impl<'s> MapSerializer<'s> {
+    fn add<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<()> {
+        value.serialize(SlotSerializer{slot: self.dict.at(key)})
+    }
+
+    fn finish(self) -> Result<()> {
+        if let Some(slot) = self.slot {
+            slot.put_value(&self.dict);
+        }
+        Ok(())
+    }
+}
+
+impl<'s> ser::SerializeMap for MapSerializer<'s> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self.key.take()
+                      .ok_or_else(|| Error::serde_error("map value without a key".to_string()))?;
+        self.add(&key, value)
+    }
+
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+impl<'s> ser::SerializeStruct for MapSerializer<'s> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.add(key, value)
+    }
+
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+
+// Serializes a tuple or struct enum variant as a dict whose single key is the variant name.
+struct VariantSerializer<'s, S> {
+    slot:    Slot<'s>,
+    variant: &'static str,
+    inner:   S,
+}
+
+impl<'s> ser::SerializeTupleVariant for VariantSerializer<'s, SeqSerializer<'s>> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.inner.add(value)
+    }
+
+    fn end(self) -> Result<()> {
+        let mut dict = MutableDict::new();
+        dict.at(self.variant).put_value(&self.inner.array);
+        self.slot.put_value(&dict);
+        Ok(())
+    }
+}
+
+impl<'s> ser::SerializeStructVariant for VariantSerializer<'s, MapSerializer<'s>> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.inner.add(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        let mut dict = MutableDict::new();
+        dict.at(self.variant).put_value(&self.inner.dict);
+        self.slot.put_value(&dict);
+        Ok(())
+    }
+}
+
+
+// Serializes a map key, which must be a string, char, or integer, to a string.
+struct KeySerializer;
+
+fn key_error<T>() -> Result<T> {
+    Err(Error::serde_error("map keys must be strings".to_string()))
+}
+
+macro_rules! key_to_string {
+    ($($method:ident: $t:ty),*) => {$(
+        fn $method(self, v: $t) -> Result<String> { Ok(v.to_string()) }
+    )*}
+}
+
+impl ser::Serializer for KeySerializer {
+    type Ok = String;
+    type Error = Error;
+    type SerializeSeq = ser::Impossible<String, Error>;
+    type SerializeTuple = ser::Impossible<String, Error>;
+    type SerializeTupleStruct = ser::Impossible<String, Error>;
+    type SerializeTupleVariant = ser::Impossible<String, Error>;
+    type SerializeMap = ser::Impossible<String, Error>;
+    type SerializeStruct = ser::Impossible<String, Error>;
+    type SerializeStructVariant = ser::Impossible<String, Error>;
+
+    key_to_string!(serialize_i8: i8, serialize_i16: i16, serialize_i32: i32, serialize_i64: i64,
+                   serialize_u8: u8, serialize_u16: u16, serialize_u32: u32, serialize_u64: u64,
+                   serialize_char: char, serialize_str: &str);
+
+    fn serialize_bool(self, _v: bool) -> Result<String>     { key_error() }
+    fn serialize_f32(self, _v: f32) -> Result<String>       { key_error() }
+    fn serialize_f64(self, _v: f64) -> Result<String>       { key_error() }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String>   { key_error() }
+    fn serialize_none(self) -> Result<String>               { key_error() }
+    fn serialize_unit(self) -> Result<String>               { key_error() }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _value: &T) -> Result<String> {
+        key_error()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
+        key_error()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32,
+                              variant: &'static str) -> Result<String> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str,
+                                                       value: &T) -> Result<String> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32,
+                                                        _variant: &'static str,
+                                                        _value: &T) -> Result<String> {
+        key_error()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        key_error()
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        key_error()
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str,
+                              _len: usize) -> Result<Self::SerializeTupleStruct> {
+        key_error()
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, _variant: &'static str,
+                               _len: usize) -> Result<Self::SerializeTupleVariant> {
+        key_error()
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        key_error()
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        key_error()
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, _variant: &'static str,
+                                _len: usize) -> Result<Self::SerializeStructVariant> {
+        key_error()
+    }
+}
+
+
+//////// ENCODER SERIALIZER:
+
+
+// Serializes a value directly into an FLEncoder, in the same form as `SlotSerializer`.
+#[derive(Clone, Copy)]
+struct EncoderSerializer {
+    encoder: FLEncoder,
+}
+
+impl EncoderSerializer {
+    // Converts the result of an FLEncoder call to a Result.
+    fn check(self, ok: bool) -> Result<()> {
+        if ok {
+            Ok(())
+        } else {
+            Err(Error::fleece_error(unsafe { FLEncoder_GetError(self.encoder) }))
+        }
+    }
+
+    fn begin_variant(self, variant: &str) -> Result<()> {
+        unsafe {
+            self.check(FLEncoder_BeginDict(self.encoder, 1))?;
+            self.check(FLEncoder_WriteKey(self.encoder, as_slice(variant)))
+        }
+    }
+}
+
+impl ser::Serializer for EncoderSerializer {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = EncoderCollection;
+    type SerializeTuple = EncoderCollection;
+    type SerializeTupleStruct = EncoderCollection;
+    type SerializeTupleVariant = EncoderCollection;
+    type SerializeMap = EncoderCollection;
+    type SerializeStruct = EncoderCollection;
+    type SerializeStructVariant = EncoderCollection;
+
+    fn serialize_bool(self, v: bool) -> Result<()>  { self.check(unsafe { FLEncoder_WriteBool(self.encoder, v) }) }
+    fn serialize_i8(self, v: i8) -> Result<()>      { self.serialize_i64(v.into()) }
+    fn serialize_i16(self, v: i16) -> Result<()>    { self.serialize_i64(v.into()) }
+    fn serialize_i32(self, v: i32) -> Result<()>    { self.serialize_i64(v.into()) }
+    fn serialize_i64(self, v: i64) -> Result<()>    { self.check(unsafe { FLEncoder_WriteInt(self.encoder, v) }) }
+    fn serialize_u8(self, v: u8) -> Result<()>      { self.serialize_i64(v.into()) }
+    fn serialize_u16(self, v: u16) -> Result<()>    { self.serialize_i64(v.into()) }
+    fn serialize_u32(self, v: u32) -> Result<()>    { self.serialize_i64(v.into()) }
+    fn serialize_u64(self, v: u64) -> Result<()>    { self.check(unsafe { FLEncoder_WriteUInt(self.encoder, v) }) }
+    fn serialize_f32(self, v: f32) -> Result<()>    { self.check(unsafe { FLEncoder_WriteFloat(self.encoder, v) }) }
+    fn serialize_f64(self, v: f64) -> Result<()>    { self.check(unsafe { FLEncoder_WriteDouble(self.encoder, v) }) }
+    fn serialize_char(self, v: char) -> Result<()>  { self.serialize_str(&v.to_string()) }
+    fn serialize_str(self, v: &str) -> Result<()>   { self.check(unsafe { FLEncoder_WriteString(self.encoder, as_slice(v)) }) }
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> { self.check(unsafe { FLEncoder_WriteData(self.encoder, bytes_as_slice(v)) }) }
+    fn serialize_none(self) -> Result<()>           { self.serialize_unit() }
+    fn serialize_unit(self) -> Result<()>           { self.check(unsafe { FLEncoder_WriteNull(self.encoder) }) }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32,
+                              variant: &'static str) -> Result<()> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str,
+                                                       value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32,
+                                                        variant: &'static str,
+                                                        value: &T) -> Result<()> {
+        self.begin_variant(variant)?;
+        value.serialize(self)?;
+        self.check(unsafe { FLEncoder_EndDict(self.encoder) })
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<EncoderCollection> {
+        self.check(unsafe { FLEncoder_BeginArray(self.encoder, len.unwrap_or(0) as size_t) })?;
+        Ok(EncoderCollection{ser: self, is_dict: false, in_variant: false})
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<EncoderCollection> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<EncoderCollection> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str,
+                               len: usize) -> Result<EncoderCollection> {
+        self.begin_variant(variant)?;
+        let collection = self.serialize_seq(Some(len))?;
+        Ok(EncoderCollection{in_variant: true, ..collection})
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<EncoderCollection> {
+        self.check(unsafe { FLEncoder_BeginDict(self.encoder, len.unwrap_or(0) as size_t) })?;
+        Ok(EncoderCollection{ser: self, is_dict: true, in_variant: false})
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<EncoderCollection> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str,
+                                len: usize) -> Result<EncoderCollection> {
+        self.begin_variant(variant)?;
+        let collection = self.serialize_map(Some(len))?;
+        Ok(EncoderCollection{in_variant: true, ..collection})
+    }
+}
+
+
+// Serializes the items of an array, or the keys and values of a dict, into an FLEncoder.
+// A collection in an enum variant is wrapped in a single-key dict, which is ended with it.
+struct EncoderCollection {
+    ser:        EncoderSerializer,
+    is_dict:    bool,
+    in_variant: bool,
+}
+
+impl EncoderCollection {
+    fn add<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(self.ser)
+    }
+
+    fn add_key(&mut self, key: &str) -> Result<()> {
+        self.ser.check(unsafe { FLEncoder_WriteKey(self.ser.encoder, as_slice(key)) })
+    }
+
+    fn finish(self) -> Result<()> {
+        let encoder = self.ser.encoder;
+        unsafe {
+            self.ser.check(if self.is_dict {FLEncoder_EndDict(encoder)} else {FLEncoder_EndArray(encoder)})?;
+            if self.in_variant {
+                self.ser.check(FLEncoder_EndDict(encoder))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for EncoderCollection {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.add(value) }
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+impl ser::SerializeTuple for EncoderCollection {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.add(value) }
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+impl ser::SerializeTupleStruct for EncoderCollection {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.add(value) }
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+impl ser::SerializeTupleVariant for EncoderCollection {
+    type Ok = ();
+    type Error = Error;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.add(value) }
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+impl ser::SerializeMap for EncoderCollection {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        let key = key.serialize(KeySerializer)?;
+        self.add_key(&key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> { self.add(value) }
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+impl ser::SerializeStruct for EncoderCollection {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.add_key(key)?;
+        self.add(value)
+    }
+
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+impl ser::SerializeStructVariant for EncoderCollection {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        self.add_key(key)?;
+        self.add(value)
+    }
+
+    fn end(self) -> Result<()> { self.finish() }
+}
+
+
+//////// QUERY ROWS:
+
+
+impl ResultSet {
+    /** Deserializes each row, as a map from column names to values, into a `T`. */
+    pub fn rows_as<T: DeserializeOwned>(self) -> RowsAs<T> {
+        RowsAs{results: self, _type: PhantomData}
+    }
+}
+
+/** An iterator over query rows deserialized as a type `T`, returned by `ResultSet::rows_as`. */
+pub struct RowsAs<T> {
+    results: ResultSet,
+    _type:   PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> Iterator for RowsAs<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        self.results.next_row().map(|row| from_value(row.as_dict().as_value()))
+    }
+}
+
+impl Query {
+    /** Runs the query and sends each row, deserialized as a `T`, to a channel. A row that fails
+        to deserialize is sent as an error. Otherwise like `execute_to_channel`. */
+    pub fn execute_to_channel_as<T: DeserializeOwned>(&self, sender: mpsc::SyncSender<Result<T>>) -> Result<u64> {
+        self.send_rows(&sender, |row, _| Ok(from_value(row.as_dict().as_value())))
+    }
+
+    /** Runs the query on a worker thread, which deserializes each row as a `T`. A row that fails
+        to deserialize produces an error, but iteration can continue past it. Otherwise like
+        `execute_in_background`. */
+    pub fn execute_in_background_as<T>(&self, buffer: usize) -> RowStream<T>
+        where T: DeserializeOwned + Send + 'static
+    {
+        self.stream_rows(buffer, |row, _| from_value(row.as_dict().as_value()))
+    }
+}
+
+
+//////// DESERIALIZER:
+
+
+// Deserializes from a Value. Enums are expected in the form written by `SlotSerializer`.
+struct ValueDeserializer<'f> {
+    value: Value<'f>,
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let value = self.value;
+        match value.get_type() {
+            ValueType::Undefined | ValueType::Null => visitor.visit_unit(),
+            ValueType::Bool   => visitor.visit_bool(value.as_bool_or_false()),
+            ValueType::Number => {
+                if !value.is_integer() {
+                    visitor.visit_f64(value.as_f64_or_0())
+                } else if unsafe { FLValue_IsUnsigned(value._ref) } {
+                    visitor.visit_u64(value.as_u64_or_0())
+                } else {
+                    visitor.visit_i64(value.as_i64_or_0())
+                }
+            }
+            ValueType::String => visitor.visit_borrowed_str(value.as_string().unwrap_or("")),
+            ValueType::Data   => visitor.visit_borrowed_bytes(value.as_data().unwrap_or(&[])),
+            ValueType::Array  => visitor.visit_seq(ArrayAccess{iter: value.as_array().iter()}),
+            ValueType::Dict   => visitor.visit_map(DictAccess{iter: value.as_dict().iter(), value: None}),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value.get_type() {
+            ValueType::Undefined | ValueType::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str,
+                                                   visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str,
+                                         _variants: &'static [&'static str],
+                                         visitor: V) -> Result<V::Value> {
+        let value = self.value;
+        match value.get_type() {
+            ValueType::String => visitor.visit_enum(value.as_string().unwrap_or("").into_deserializer()),
+            ValueType::Dict if value.as_dict().count() == 1 => {
+                let (variant, content) = value.as_dict().iter().next().unwrap();
+                visitor.visit_enum(EnumAccess{variant, content})
+            }
+            _ => Err(de::Error::custom("expected a string or a single-key dict for an enum")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+
+struct ArrayAccess<'f> {
+    iter: ArrayIterator<'f>,
+}
+
+impl<'de> de::SeqAccess<'de> for ArrayAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<S: de::DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>> {
+        match self.iter.next() {
+            Some(value) => seed.deserialize(ValueDeserializer{value}).map(Some),
+            None        => Ok(None),
+        }
+    }
+}
+
+
+struct DictAccess<'f> {
+    iter:  DictIterator<'f>,
+    value: Option<Value<'f>>,
+}
+
+impl<'de> de::MapAccess<'de> for DictAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<S: de::DeserializeSeed<'de>>(&mut self, seed: S) -> Result<Option<S::Value>> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(de::value::BorrowedStrDeserializer::new(key)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<S: de::DeserializeSeed<'de>>(&mut self, seed: S) -> Result<S::Value> {
+        let value = self.value.take()
+                        .ok_or_else(|| Error::serde_error("map value without a key".to_string()))?;
+        seed.deserialize(ValueDeserializer{value})
+    }
+}
+
+
+struct EnumAccess<'f> {
+    variant: &'f str,
+    content: Value<'f>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess<'de> {
+    type Error = Error;
+    type Variant = ValueDeserializer<'de>;
+
+    fn variant_seed<S: de::DeserializeSeed<'de>>(self, seed: S) -> Result<(S::Value, Self::Variant)> {
+        let variant = seed.deserialize(de::value::BorrowedStrDeserializer::<Error>::new(self.variant))?;
+        Ok((variant, ValueDeserializer{value: self.content}))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<S: de::DeserializeSeed<'de>>(self, seed: S) -> Result<S::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str],
+                                       visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
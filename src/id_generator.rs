@@ -0,0 +1,238 @@
+// Couchbase Lite document ID generators
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+
+/** A strategy for generating the IDs of new documents.
+    Set one on a `Database` with `set_id_generator`, or pass one to
+    `Document::new_with_generator`. */
+pub trait IdGenerator : Send + Sync {
+    /** Returns a new, unique document ID. */
+    fn generate_id(&self) -> String;
+}
+
+
+//////// DATABASE'S ID GENERATOR API:
+
+
+impl Database {
+    /** Sets the generator used by `new_document` to assign IDs. */
+    pub fn set_id_generator<G: IdGenerator + 'static>(&self, generator: G) {
+        *self._extras.id_generator.write().unwrap() = Some(Arc::new(generator));
+    }
+
+    /** Removes the ID generator, going back to the random IDs assigned by `Document::new`. */
+    pub fn clear_id_generator(&self) {
+        *self._extras.id_generator.write().unwrap() = None;
+    }
+
+    /** Creates a new, empty document in memory, with an ID from the database's ID generator,
+        or a random ID if it has none. It will not be added to the database until saved. */
+    pub fn new_document(&self) -> Document {
+        let generator = self._extras.id_generator.read().unwrap().clone();
+        match generator {
+            Some(g) => Document::new_with_generator(&*g),
+            None    => Document::new(),
+        }
+    }
+}
+
+
+//////// UUIDS:
+
+
+/** Generates random (version 4) UUIDs, e.g. `"1b4e28ba-2fa1-41d2-883f-0016d3cca427"`. */
+#[derive(Debug, Default)]
+pub struct UuidV4Generator;
+
+impl IdGenerator for UuidV4Generator {
+    fn generate_id(&self) -> String {
+        let mut bytes: [u8; 16] = rand::random();
+        bytes[6] = 0x40 | (bytes[6] & 0x0F);
+        bytes[8] = 0x80 | (bytes[8] & 0x3F);
+        return format_uuid(&bytes);
+    }
+}
+
+
+/** Generates time-ordered (version 7) UUIDs, e.g. `"01890a5d-ac96-774b-bcce-b302099a8057"`.
+    IDs generated later sort after earlier ones, even within the same millisecond, so new
+    documents are clustered together in the database's ID order. */
+#[derive(Debug, Default)]
+pub struct UuidV7Generator {
+    last: Mutex<(u64, u16)>,    // Timestamp and counter of the last ID generated
+}
+
+impl UuidV7Generator {
+    pub fn new() -> UuidV7Generator {
+        UuidV7Generator::default()
+    }
+}
+
+impl IdGenerator for UuidV7Generator {
+    fn generate_id(&self) -> String {
+        let mut bytes: [u8; 16] = rand::random();
+        let (time, counter) = {
+            let mut last = self.last.lock().unwrap();
+            let mut time = now_millis();
+            let counter;
+            if time > last.0 {
+                // Start each millisecond at a random counter, leaving room to count upwards:
+                counter = u16::from_be_bytes([bytes[6], bytes[7]]) & 0x07FF;
+            } else if last.1 < 0x0FFF {
+                time = last.0;
+                counter = last.1 + 1;
+            } else {
+                time = last.0 + 1;
+                counter = 0;
+            }
+            *last = (time, counter);
+            (time, counter)
+        };
+        bytes[..6].copy_from_slice(&time.to_be_bytes()[2..]);
+        bytes[6] = 0x70 | (counter >> 8) as u8;
+        bytes[7] = counter as u8;
+        bytes[8] = 0x80 | (bytes[8] & 0x3F);
+        return format_uuid(&bytes);
+    }
+}
+
+
+fn format_uuid(bytes: &[u8; 16]) -> String {
+    let mut uuid = String::with_capacity(36);
+    for (i, b) in bytes.iter().enumerate() {
+        if i == 4 || i == 6 || i == 8 || i == 10 {
+            uuid.push('-');
+        }
+        uuid.push_str(&format!("{:02x}", b));
+    }
+    return uuid;
+}
+
+
+//////// ULIDS:
+
+
+/** Generates ULIDs: 26-character, time-ordered, case-insensitive IDs in Crockford's base32,
+    e.g. `"01ARZ3NDEKTSV4RRFFQ69G5FAV"`. IDs generated later sort after earlier ones,
+    even within the same millisecond. */
+#[derive(Debug, Default)]
+pub struct UlidGenerator {
+    last: Mutex<(u64, u128)>,   // Timestamp and random part of the last ID generated
+}
+
+const CROCKFORD_BASE32 : &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const ULID_RANDOM_MASK : u128 = (1 << 80) - 1;
+
+impl UlidGenerator {
+    pub fn new() -> UlidGenerator {
+        UlidGenerator::default()
+    }
+}
+
+impl IdGenerator for UlidGenerator {
+    fn generate_id(&self) -> String {
+        let (time, random) = {
+            let mut last = self.last.lock().unwrap();
+            let mut time = now_millis();
+            let mut random = rand::random::<u128>() & ULID_RANDOM_MASK;
+            if time <= last.0 {
+                time = last.0;
+                random = (last.1 + 1) & ULID_RANDOM_MASK;
+                if random == 0 {
+                    time += 1;
+                }
+            }
+            *last = (time, random);
+            (time, random)
+        };
+        let value = ((time as u128) << 80) | random;
+        return (0..26).map(|i| {
+            let digit = (value >> (125 - 5 * i)) & 0x1F;
+            CROCKFORD_BASE32[digit as usize] as char
+        }).collect();
+    }
+}
+
+
+fn now_millis() -> u64 {
+    Timestamp::from(SystemTime::now()).millis() as u64
+}
+
+
+//////// COUNTERS:
+
+
+/** Generates IDs of the form `"<type>::<counter>"`, e.g. `"order::42"`.
+    The counter lives in memory, so a generator for a database that already has documents of
+    this type should be created with `resuming` or `starting_at`. */
+#[derive(Debug)]
+pub struct CounterIdGenerator {
+    prefix:  String,
+    counter: AtomicU64,
+}
+
+impl CounterIdGenerator {
+    /** Creates a generator whose first ID is `"<doc_type>::1"`. */
+    pub fn new(doc_type: &str) -> CounterIdGenerator {
+        CounterIdGenerator::starting_at(doc_type, 1)
+    }
+
+    /** Creates a generator whose first ID is `"<doc_type>::<next>"`. */
+    pub fn starting_at(doc_type: &str, next: u64) -> CounterIdGenerator {
+        CounterIdGenerator{prefix: format!("{}::", doc_type), counter: AtomicU64::new(next)}
+    }
+
+    /** Creates a generator that continues after the highest `"<doc_type>::<counter>"` ID
+        already in the database. */
+    pub fn resuming(db: &Database, doc_type: &str) -> Result<CounterIdGenerator> {
+        let generator = CounterIdGenerator::new(doc_type);
+        // A range rather than `LIKE`, since the type may contain the wildcards `%` and `_`.
+        // The IDs starting with "<type>::" are the ones from "<type>::" up to "<type>:;":
+        let query = Query::new(db, QueryLanguage::N1QL,
+                               "SELECT META().id FROM _ WHERE META().id >= $start AND META().id < $end")?;
+        let mut params = MutableDict::new();
+        params.at("start").put_string(&generator.prefix);
+        params.at("end").put_string(format!("{}:;", doc_type));
+        query.set_parameters(params);
+
+        let mut highest = 0;
+        let mut results = query.execute()?;
+        while let Some(row) = results.next_row() {
+            let id = row.get(0).as_string().unwrap_or("");
+            if id.starts_with(&generator.prefix) {
+                if let Ok(n) = id[generator.prefix.len()..].parse::<u64>() {
+                    highest = highest.max(n);
+                }
+            }
+        }
+        generator.counter.store(highest + 1, Ordering::SeqCst);
+        return Ok(generator);
+    }
+}
+
+impl IdGenerator for CounterIdGenerator {
+    fn generate_id(&self) -> String {
+        format!("{}{}", self.prefix, self.counter.fetch_add(1, Ordering::SeqCst))
+    }
+}
@@ -0,0 +1,91 @@
+// Couchbase Lite index API
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+use super::slice::*;
+use super::c_api::*;
+
+
+/** Options for a full-text index. */
+#[derive(Debug, Clone, Default)]
+pub struct FullTextIndexOptions {
+    pub ignore_accents: bool,           // Should diacritical marks be ignored when matching?
+    pub language: Option<String>,       // Dominant language, as an ISO-639 code or English name,
+                                        // e.g. "en" or "french". Enables stemming & stop-words.
+}
+
+
+impl Database {
+    /** Creates a value index, which speeds up queries that test or sort by the indexed
+        expressions. The expressions are given in the query language: in N1QL as a
+        comma-separated list, e.g. `"lastName, firstName"`; in JSON as an array of expressions,
+        e.g. `[[".lastName"],[".firstName"]]`.
+
+        Creating an index that already exists with the same expressions does nothing; with
+        different expressions, it replaces the existing index. */
+    pub fn create_value_index(&mut self, name: &str, language: QueryLanguage, expressions: &str) -> Result<()> {
+        let config = CBLValueIndexConfiguration {
+            expressionLanguage: language as CBLQueryLanguage,
+            expressions:        as_slice(expressions),
+        };
+        unsafe {
+            check_bool(|error| CBLDatabase_CreateValueIndex(self._ref, as_slice(name), config, error))?;
+        }
+        self.clear_query_cache();
+        Ok(())
+    }
+
+    /** Creates a full-text index, which lets queries search the text of the indexed expressions
+        with `full_text::matches`, or with `search`. The expressions are given as for
+        `create_value_index`. */
+    pub fn create_full_text_index(&mut self, name: &str, language: QueryLanguage, expressions: &str,
+                                  options: &FullTextIndexOptions) -> Result<()> {
+        let config = CBLFullTextIndexConfiguration {
+            expressionLanguage: language as CBLQueryLanguage,
+            expressions:        as_slice(expressions),
+            ignoreAccents:      options.ignore_accents,
+            language:           options.language.as_ref().map_or(NULL_SLICE, |l| as_slice(l)),
+        };
+        unsafe {
+            check_bool(|error| CBLDatabase_CreateFullTextIndex(self._ref, as_slice(name), config, error))?;
+        }
+        self.clear_query_cache();
+        Ok(())
+    }
+
+    /** Deletes an index. Deleting an index that doesn't exist does nothing. */
+    pub fn delete_index(&mut self, name: &str) -> Result<()> {
+        unsafe {
+            check_bool(|error| CBLDatabase_DeleteIndex(self._ref, as_slice(name), error))?;
+        }
+        self.clear_query_cache();
+        Ok(())
+    }
+
+    /** Returns the names of the database's indexes. */
+    pub fn index_names(&self) -> Vec<String> {
+        unsafe {
+            // The array returned by `CBLDatabase_GetIndexNames` is retained, so release it:
+            let names_ref = CBLDatabase_GetIndexNames(self._ref);
+            let names = Array::wrap(names_ref, self).iter()
+                            .filter_map(|name| name.as_string().map(str::to_string))
+                            .collect();
+            FLValue_Release(names_ref as FLValue);
+            return names;
+        }
+    }
+}
@@ -19,47 +19,198 @@
 //#![allow(dead_code)]
 
 #[macro_use] extern crate enum_primitive;
+#[cfg(feature = "chrono")] extern crate chrono;
+extern crate rand;
+#[cfg(feature = "serde")] extern crate serde;
+#[cfg(feature = "macros")] extern crate couchbase_lite_macros;
 
 pub mod blob;
 pub mod database;
 pub mod document;
 pub mod error;
+pub mod export;
 pub mod fleece;
+pub mod fleece_convert;
+pub mod fleece_encoder;
 pub mod fleece_mutable;
+#[cfg(feature = "serde")] pub mod fleece_serde;
+pub mod id_generator;
+pub mod index;
 pub mod logging;
+pub mod pagination;
+#[cfg(feature = "enterprise")] pub mod prediction;
 pub mod query;
+pub mod query_builder;
+pub mod query_cache;
+pub mod query_plan;
+pub mod query_stream;
 pub mod replicator;
+pub mod search;
+pub mod validation;
+#[cfg(feature = "vector-search")] pub mod vector;
 
+mod base64;
 mod slice;
 mod c_api;
 
 use self::c_api::*;
 
+use std::any::Any;
+use std::convert::TryFrom;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 
 //////// RE-EXPORT:
 
 
+#[cfg(feature = "macros")] pub use couchbase_lite_macros::cbl_query;
 pub use blob::*;
 pub use database::*;
 pub use document::*;
 pub use error::*;
+pub use export::*;
 pub use fleece::*;
+pub use fleece_convert::*;
+pub use fleece_encoder::*;
 pub use fleece_mutable::*;
+#[cfg(feature = "serde")] pub use fleece_serde::*;
+pub use id_generator::*;
+pub use index::*;
+pub use pagination::*;
+#[cfg(feature = "enterprise")] pub use prediction::*;
 pub use query::*;
+pub use query_builder::*;
+pub use query_cache::*;
+pub use query_plan::*;
+pub use query_stream::*;
 pub use replicator::*;
+pub use search::*;
+pub use validation::*;
+#[cfg(feature = "vector-search")] pub use vector::*;
 
 
 //////// TOP-LEVEL TYPES:
 
 
 /// A time value for document expiration. Defined as milliseconds since the Unix epoch (1/1/1970.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Timestamp(i64);
 
 
+impl Timestamp {
+    /** Creates a timestamp from a number of milliseconds since the Unix epoch. */
+    pub fn from_millis(millis: i64) -> Timestamp {
+        Timestamp(millis)
+    }
+
+    /** Returns the current time. */
+    pub fn now() -> Timestamp {
+        Timestamp::from(SystemTime::now())
+    }
+
+    /** Returns the number of milliseconds since the Unix epoch. */
+    pub fn millis(&self) -> i64 {
+        self.0
+    }
+
+    /** Returns the time elapsed from `earlier` to this timestamp, or None if `earlier` is
+        actually later. */
+    pub fn duration_since(&self, earlier: Timestamp) -> Option<Duration> {
+        if self.0 >= earlier.0 {
+            Some(Duration::from_millis(self.0.abs_diff(earlier.0)))
+        } else {
+            None
+        }
+    }
+
+    /** Returns the timestamp `duration` later, or None if that's out of range. */
+    pub fn checked_add(&self, duration: Duration) -> Option<Timestamp> {
+        let millis = i64::try_from(duration.as_millis()).ok()?;
+        self.0.checked_add(millis).map(Timestamp)
+    }
+
+    /** Returns the timestamp `duration` earlier, or None if that's out of range. */
+    pub fn checked_sub(&self, duration: Duration) -> Option<Timestamp> {
+        let millis = i64::try_from(duration.as_millis()).ok()?;
+        self.0.checked_sub(millis).map(Timestamp)
+    }
+}
+
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Timestamp {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => Timestamp(i64::try_from(after.as_millis()).unwrap_or(i64::MAX)),
+            Err(before) => Timestamp(i64::try_from(before.duration().as_millis()).map_or(i64::MIN, |ms| -ms)),
+        }
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(time: Timestamp) -> SystemTime {
+        if time.0 >= 0 {
+            UNIX_EPOCH + Duration::from_millis(time.0 as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_millis(time.0.unsigned_abs())
+        }
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl<TZ: chrono::TimeZone> From<chrono::DateTime<TZ>> for Timestamp {
+    fn from(time: chrono::DateTime<TZ>) -> Timestamp {
+        Timestamp(time.timestamp_millis())
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<Timestamp> for chrono::DateTime<chrono::Utc> {
+    fn from(time: Timestamp) -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::<chrono::Utc>::from(SystemTime::from(time))
+    }
+}
+
+// Adding or subtracting a `Duration` saturates at the ends of the range; see `checked_add`.
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+    fn add(self, duration: Duration) -> Timestamp {
+        self.checked_add(duration).unwrap_or(Timestamp(i64::MAX))
+    }
+}
+
+impl Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+    fn sub(self, duration: Duration) -> Timestamp {
+        self.checked_sub(duration).unwrap_or(Timestamp(i64::MIN))
+    }
+}
+
+impl AddAssign<Duration> for Timestamp {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl SubAssign<Duration> for Timestamp {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+
 /// An opaque token representing a registered listener.
 /// When this object is dropped, the listener function will not be called again.
 pub struct ListenerToken {
-    _ref: *mut CBLListenerToken
+    _ref: *mut CBLListenerToken,
+    _context: Option<Box<dyn Any>>,     // Data the C listener callback points to
+}
+
+
+impl ListenerToken {
+    pub(crate) fn new(_ref: *mut CBLListenerToken, context: Option<Box<dyn Any>>) -> ListenerToken {
+        ListenerToken{_ref, _context: context}
+    }
 }
 
 
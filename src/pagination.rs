@@ -0,0 +1,190 @@
+// Couchbase Lite query pagination
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+use super::base64;
+
+
+/** A cursor that runs a query one page at a time, by binding its `$limit` parameter to the
+    page size, and either its `$offset` parameter (see `Query::paginate`) or its `$after`
+    parameter (see `Query::paginate_by_key`) to the position of the next page.
+
+    Since the cursor rebinds those parameters before each page, it shouldn't be interleaved with
+    other uses of the same `Query`.
+
+    A cursor's position can be saved as an opaque URL-safe string with `token`, and restored
+    later with `Query::resume_pages`. */
+pub struct QueryPages<'q> {
+    query:     &'q Query,
+    page_size: usize,
+    position:  PagePosition,
+    done:      bool,
+}
+
+enum PagePosition {
+    Offset(usize),                                      // Number of rows already returned
+    After{key_path: String, last: Option<Fleece>},      // Ordering key of the last row returned
+}
+
+
+impl Query {
+    /** Returns a cursor that pages through the query's results using `LIMIT $limit OFFSET $offset`,
+        which the query must contain. This is simple, but each page has to skip over all the
+        rows before it, so deep pages get slower; `paginate_by_key` avoids that. */
+    pub fn paginate(&self, page_size: usize) -> QueryPages<'_> {
+        QueryPages::new(self, page_size, PagePosition::Offset(0))
+    }
+
+    /** Returns a cursor that pages through the query's results by "keyset": each page starts after
+        the value of `key_path` in the last row of the previous page. `key_path` is a column
+        name, optionally followed by a path within it, e.g. `name` or `address.city`.
+
+        The query must order by that key, which should be unique, filter on `$after` and use
+        `LIMIT $limit`. For the first page `$after` is null, so the filter has to allow that:
+        ```text
+        SELECT meta().id, name FROM _ WHERE $after IS NULL OR name > $after ORDER BY name LIMIT $limit
+        ``` */
+    pub fn paginate_by_key(&self, page_size: usize, key_path: &str) -> QueryPages<'_> {
+        QueryPages::new(self, page_size, PagePosition::After{key_path: key_path.to_string(),
+                                                             last: None})
+    }
+
+    /** Returns a cursor positioned where one was when its `token` was created.
+        Fails with `InvalidParameter` if the token isn't valid. */
+    pub fn resume_pages(&self, token: &str) -> Result<QueryPages<'_>> {
+        let invalid = || Error::cbl_error(CouchbaseLiteError::InvalidParameter);
+        let json = base64::decode(token).and_then(|bytes| String::from_utf8(bytes).ok())
+                                       .ok_or_else(invalid)?;
+        let state = Fleece::parse_json(&json).map_err(|_| invalid())?;
+        let state = state.as_dict();
+
+        let page_size = state.get("size").as_u64().ok_or_else(invalid)? as usize;
+        let position = if let Some(key_path) = state.get("key").as_string() {
+            let after = state.get("after");
+            let last = if !after { None }
+                       else { Some(Fleece::copy_of(&after)?) };
+            PagePosition::After{key_path: key_path.to_string(), last}
+        } else {
+            PagePosition::Offset(state.get("offset").as_u64().ok_or_else(invalid)? as usize)
+        };
+        let mut pages = QueryPages::new(self, page_size, position);
+        pages.done = state.get("done").as_bool_or_false();
+        Ok(pages)
+    }
+}
+
+
+impl<'q> QueryPages<'q> {
+    fn new(query: &'q Query, page_size: usize, position: PagePosition) -> QueryPages<'q> {
+        QueryPages{query, page_size: page_size.max(1), position, done: false}
+    }
+
+    /** The number of rows per page. */
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /** Returns false once a page shorter than the page size, or an empty page, has been returned. */
+    pub fn has_more(&self) -> bool {
+        !self.done
+    }
+
+    /** Runs the query for the next page, returning its rows, or `None` if there are no more.
+        Fails with `InvalidQueryParam` if the query doesn't use the parameters this kind of
+        cursor binds. */
+    pub fn next_page(&mut self) -> Result<Option<Vec<OwnedRow>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let required: &[&str] = match self.position {
+            PagePosition::Offset(_)   => &["limit", "offset"],
+            PagePosition::After{..}   => &["limit", "after"],
+        };
+        let names = self.query.parameter_names();
+        if !required.iter().all(|name| names.contains(name)) {
+            return Err(Error::cbl_error(CouchbaseLiteError::InvalidQueryParam));
+        }
+
+        self.query.bind("limit", self.page_size as u64);
+        match self.position {
+            PagePosition::Offset(offset) => {
+                self.query.bind("offset", offset as u64);
+            }
+            PagePosition::After{last: Some(ref last), ..} => {
+                self.query.bind("after", last.root());
+            }
+            PagePosition::After{last: None, ..} => {
+                self.query.bind("after", Option::<bool>::None);
+            }
+        }
+
+        let rows = self.query.execute()?.collect::<Result<Vec<OwnedRow>>>()?;
+        self.done = rows.len() < self.page_size;
+        match self.position {
+            PagePosition::Offset(ref mut offset) => {
+                *offset += rows.len();
+            }
+            PagePosition::After{ref key_path, ref mut last} => {
+                if let Some(row) = rows.last() {
+                    *last = Some(Fleece::copy_of(&row.get_path(key_path)?)?);
+                }
+            }
+        }
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(rows))
+    }
+
+    /** Returns an opaque, URL-safe string encoding the cursor's position, for `Query::resume_pages`.
+        It doesn't identify the query, so it must be resumed with the same one. */
+    pub fn token(&self) -> String {
+        let mut json = format!("{{\"size\":{}", self.page_size);
+        match self.position {
+            PagePosition::Offset(offset) => {
+                json += &format!(",\"offset\":{}", offset);
+            }
+            PagePosition::After{ref key_path, ref last} => {
+                json += ",\"key\":";
+                write_json_string(key_path, &mut json);
+                if let Some(last) = last {
+                    json += ",\"after\":";
+                    json += &last.root().to_json();
+                }
+            }
+        }
+        if self.done {
+            json += ",\"done\":true";
+        }
+        json += "}";
+        base64::encode(json.as_bytes(), true)
+    }
+}
+
+impl<'q> Iterator for QueryPages<'q> {
+    type Item = Result<Vec<OwnedRow>>;
+
+    fn next(&mut self) -> Option<Result<Vec<OwnedRow>>> {
+        match self.next_page() {
+            Ok(page) => page.map(Ok),
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
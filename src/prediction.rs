@@ -0,0 +1,114 @@
+// Couchbase Lite predictive models (Enterprise Edition)
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+use super::slice::*;
+use super::c_api::*;
+
+use std::any::Any;
+use std::ffi::c_void;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::{mem, ptr};
+
+
+/** A machine-learning model, or any other function, that can be called from a query with
+    `PREDICTION(model, input)` once it's been registered with `register_model`.
+
+    The model may be called on any thread, and concurrently. */
+pub trait PredictiveModel : Send + Sync {
+    /** Returns the prediction for an input dictionary, or None if there's no prediction (as
+        when the input is missing a property the model requires.) In a query, None is MISSING. */
+    fn predict(&self, input: &Dict) -> Option<MutableDict>;
+}
+
+/** A function can be used as a model. */
+impl<F> PredictiveModel for F
+    where F: Fn(&Dict) -> Option<MutableDict> + Send + Sync
+{
+    fn predict(&self, input: &Dict) -> Option<MutableDict> {
+        self(input)
+    }
+}
+
+
+/** Registers a predictive model under a name, replacing any model already registered with
+    that name. The model is dropped when it's unregistered or replaced.
+
+    If the model panics, the panic is caught and logged, and the prediction is MISSING. */
+pub fn register_model<M: PredictiveModel + 'static>(name: &str, model: M) {
+    let context = Box::new(RegisteredModel{name: name.to_string(), model: Box::new(model)});
+    unsafe {
+        CBL_RegisterPredictiveModel(as_slice(name), CBLPredictiveModel{
+            context:      Box::into_raw(context) as *mut c_void,
+            prediction:   Some(invoke_prediction),
+            unregistered: Some(release_model),
+        });
+    }
+}
+
+/** Unregisters the predictive model with this name, if any. Queries that use it will fail. */
+pub fn unregister_model(name: &str) {
+    unsafe { CBL_UnregisterPredictiveModel(as_slice(name)) }
+}
+
+
+//////// INTERNALS:
+
+
+struct RegisteredModel {
+    name:  String,
+    model: Box<dyn PredictiveModel>,
+}
+
+unsafe extern "C" fn invoke_prediction(context: *mut c_void, input: FLDict) -> FLMutableDict {
+    let registered = &*(context as *const RegisteredModel);
+    let input = Dict{_ref: input, _owner: PhantomData};
+    // A panic must not unwind into C:
+    match panic::catch_unwind(AssertUnwindSafe(|| registered.model.predict(&input))) {
+        Ok(Some(output)) => {
+            // The caller takes over our reference to the result:
+            let result = output._ref;
+            mem::forget(output);
+            result
+        }
+        Ok(None) => ptr::null_mut(),
+        Err(payload) => {
+            logging::write(logging::Domain::Query, logging::Level::Warning,
+                           &format!("Predictive model '{}' panicked: {}",
+                                    registered.name, panic_message(&*payload)));
+            ptr::null_mut()
+        }
+    }
+}
+
+unsafe extern "C" fn release_model(context: *mut c_void) {
+    let registered = Box::from_raw(context as *mut RegisteredModel);
+    let name = registered.name.clone();
+    // The model's destructor must not unwind into C either:
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(move || drop(registered))) {
+        logging::write(logging::Domain::Query, logging::Level::Warning,
+                       &format!("Predictive model '{}' panicked when released: {}",
+                                name, panic_message(&*payload)));
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload.downcast_ref::<&str>().map(|s| s.to_string())
+           .or_else(|| payload.downcast_ref::<String>().cloned())
+           .unwrap_or_default()
+}
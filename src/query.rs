@@ -19,11 +19,16 @@ use super::*;
 use super::slice::*;
 use super::c_api::*;
 
+use std::ffi::c_void;
+use std::fmt;
 use std::marker::PhantomData;
 use std::os::raw::c_uint;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
 
 
 /** Query languages. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum QueryLanguage {
     JSON,   // JSON query schema: github.com/couchbase/couchbase-lite-core/wiki/JSON-Query-Schema
     N1QL,   // N1QL syntax: docs.couchbase.com/server/6.0/n1ql/n1ql-language-reference/index.html
@@ -32,7 +37,11 @@ pub enum QueryLanguage {
 
 /** A compiled database query. */
 pub struct Query {
-    _ref: *mut CBLQuery
+    _ref: *mut CBLQuery,
+    _db: *mut CBLDatabase,          // Retained; for `snapshot`
+    language: QueryLanguage,
+    source: String,
+    parameter_names: Vec<String>,   // Names of the `$PARAM`s in the source
 }
 
 impl Query {
@@ -40,19 +49,49 @@ impl Query {
         This is fast, but not instantaneous. If you need to run the same query many times, keep the
         `Query` around instead of compiling it each time. If you need to run related queries
         with only some values different, create one query with placeholder parameter(s), and substitute
-        the desired value(s) with `set_parameters` before each time you run the query. */
+        the desired value(s) with `set_parameters` before each time you run the query.
+        If the query has a syntax error, the error's `query_location` tells where it is, and its
+        `Display` form points to it:
+        ```text
+        <message>, at line 1, column 10:
+        select * frm _
+                 ^
+        ``` */
     pub fn new(db: &Database, language: QueryLanguage, str: &str) -> Result<Query> {
+        Query::compile(db._ref, language, str)
+    }
+
+    fn compile(db: *mut CBLDatabase, language: QueryLanguage, str: &str) -> Result<Query> {
         unsafe {
-            let mut pos: i32 = 0;
+            let parameter_names = match language {
+                QueryLanguage::JSON => json_parameter_names(str),
+                QueryLanguage::N1QL => n1ql_parameter_names(str),
+            };
+            let mut pos: i32 = -1;
             let mut err = CBLError::default();
-            let q = CBLDatabase_CreateQuery(db._ref, language as CBLQueryLanguage, as_slice(str),
+            let q = CBLDatabase_CreateQuery(db, language as CBLQueryLanguage, as_slice(str),
                                             &mut pos, &mut err);
             if q.is_null() {
-                // TODO: Return the error pos somehow
-                return failure(err);
+                let mut error = Error::new(&err);
+                if pos >= 0 {
+                    error.query_location = Some(Box::new(QueryErrorLocation::new(str, pos as usize)));
+                }
+                return Err(error);
             }
-            return Ok(Query{_ref: q});
+            return Ok(Query{_ref: q, _db: retain(db), language, source: str.to_string(),
+                            parameter_names});
+        }
+    }
+
+    // Compiles a separate copy of the query, with a copy of the current parameters, so that
+    // changing either query's parameters doesn't affect the other.
+    pub(crate) fn snapshot(&self) -> Result<Query> {
+        let query = Query::compile(self._db, self.language, &self.source)?;
+        let parameters = self.parameters();
+        if !parameters._ref.is_null() {
+            query.set_parameters(parameters.mutable_copy());
         }
+        Ok(query)
     }
 
     /** Assigns values to the query's parameters.
@@ -68,6 +107,32 @@ impl Query {
         }
     }
 
+    /** Binds a value to one of the query's parameters, keeping the other parameters' values.
+        The name doesn't include the `$`. Returns `self`, so calls can be chained:
+        `query.bind("min", 18).bind("city", "Paris")`. */
+    pub fn bind<T: ToFleece>(&self, name: &str, value: T) -> &Self {
+        let current = self.parameters();
+        let mut parameters = if current._ref.is_null() { MutableDict::new() }
+                             else { current.mutable_copy() };
+        parameters.at(name).put(value);
+        self.set_parameters(parameters);
+        self
+    }
+
+    /** Returns the names of the parameters used in the query source, without the `$`. */
+    pub fn parameter_names(&self) -> Vec<&str> {
+        self.parameter_names.iter().map(|name| name.as_str()).collect()
+    }
+
+    /** Returns the names of the query's parameters that don't have a value yet. */
+    pub fn unbound_parameters(&self) -> Vec<&str> {
+        let parameters = self.parameters();
+        self.parameter_names.iter()
+            .filter(|name| !parameters.get(name))
+            .map(|name| name.as_str())
+            .collect()
+    }
+
     /** Returns the query's current parameter bindings, if any. */
     pub fn parameters(&self) -> Dict {
         unsafe { return Dict{_ref: CBLQuery_Parameters(self._ref), _owner: PhantomData}; }
@@ -82,15 +147,19 @@ impl Query {
     }
 
     /** Runs the query, returning the results as a `ResultSet` object, which is an iterator
-        of `Row` objects, each of which has column values. */
+        of `Row` objects, each of which has column values.
+        If any parameter has no value, fails with `InvalidQueryParam`; see `unbound_parameters`. */
     pub fn execute(&self) -> Result<ResultSet> {
+        if !self.unbound_parameters().is_empty() {
+            return Err(Error::cbl_error(CouchbaseLiteError::InvalidQueryParam));
+        }
         unsafe {
             let mut err = CBLError::default();
             let r = CBLQuery_Execute(self._ref, &mut err);
             if r.is_null() {
                 return failure(err);
             }
-            return Ok(ResultSet{_ref: r});
+            return Ok(ResultSet{_ref: r, columns: None});
         }
     }
 
@@ -116,40 +185,322 @@ impl Query {
     }
 }
 
+
+// Finds the `$NAME` parameters in N1QL source, skipping string literals, quoted identifiers
+// and comments.
+fn n1ql_parameter_names(source: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' | '`' => {
+                while let Some(d) = chars.next() {
+                    if d == '\\' {
+                        chars.next();
+                    } else if d == c {
+                        break;
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                while let Some(d) = chars.next() {
+                    if d == '\n' { break; }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut star = false;
+                while let Some(d) = chars.next() {
+                    if star && d == '/' { break; }
+                    star = d == '*';
+                }
+            }
+            '$' => {
+                let mut name = String::new();
+                while let Some(&d) = chars.peek() {
+                    if !(d.is_alphanumeric() || d == '_') { break; }
+                    name.push(d);
+                    chars.next();
+                }
+                if !name.is_empty() && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            _ => { }
+        }
+    }
+    return names;
+}
+
+// Finds the `["$NAME"]` parameters in JSON query source.
+fn json_parameter_names(source: &str) -> Vec<String> {
+    fn visit(value: Value, names: &mut Vec<String>) {
+        match value.get_type() {
+            ValueType::Array => {
+                let array = value.as_array();
+                if let Some(op) = array.get(0).as_string() {
+                    if op.starts_with('$') && op.len() > 1 && !names.iter().any(|n| n == &op[1..]) {
+                        names.push(op[1..].to_string());
+                    }
+                }
+                for item in array.iter() {
+                    visit(item, names);
+                }
+            }
+            ValueType::Dict => {
+                for (_, item) in value.as_dict().iter() {
+                    visit(item, names);
+                }
+            }
+            _ => { }
+        }
+    }
+
+    let mut names = Vec::new();
+    if let Ok(doc) = Fleece::parse_json(source) {
+        visit(doc.root(), &mut names);
+    }
+    return names;
+}
+
+
+/** Creates a `MutableDict` of query parameters:
+    ```ignore
+    query.set_parameters(params!{ "min" => 18, "tags" => vec!["a"] });
+    ```
+    Any value that implements `ToFleece` can be used. */
+#[macro_export]
+macro_rules! params {
+    ($($key:expr => $value:expr),* $(,)?) => {{
+        let mut parameters = $crate::MutableDict::new();
+        $( parameters.at($key).put($value); )*
+        parameters
+    }};
+}
+
+
+//////// QUERY ERRORS:
+
+
+/** The location of a syntax error in a query's source, from `Error::query_location`.
+    The `Display` form shows the line and column, then the line of source with a caret under
+    the error. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryErrorLocation {
+    pub offset:  usize,     // Byte offset in the source
+    pub line:    usize,     // Line number, starting at 1
+    pub column:  usize,     // Column (in characters) within the line, starting at 1
+    pub snippet: String,    // The line of source containing the error
+}
+
+impl QueryErrorLocation {
+    fn new(source: &str, offset: usize) -> QueryErrorLocation {
+        let mut offset = offset.min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[offset..].find('\n').map_or(source.len(), |i| offset + i);
+        QueryErrorLocation {
+            offset,
+            line:    source[..offset].matches('\n').count() + 1,
+            column:  source[line_start..offset].chars().count() + 1,
+            snippet: source[line_start..line_end].trim_end_matches('\r').to_string(),
+        }
+    }
+}
+
+impl fmt::Display for QueryErrorLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Indent the caret with the same tabs as the snippet, so it lines up:
+        let indent: String = self.snippet.chars().take(self.column - 1)
+                                 .map(|c| if c == '\t' {'\t'} else {' '}).collect();
+        write!(f, "line {}, column {}:\n{}\n{}^", self.line, self.column, self.snippet, indent)
+    }
+}
+
+
+//////// LIVE QUERIES:
+
+
+type QueryChangeListener = Box<dyn Fn(Result<ResultSet>) + Send + Sync>;
+
+impl Query {
+    /** Registers a change listener, which turns this into a "live query".
+        The query runs in the background, and the listener is called with the results, and then
+        called again with fresh results every time they change. The listener is called on a
+        background thread, unless the database's notifications are buffered.
+        The listener stops being called when the returned token is dropped. */
+    pub fn add_change_listener<F>(&self, listener: F) -> ListenerToken
+        where F: Fn(Result<ResultSet>) + Send + Sync + 'static
+    {
+        let context: Box<QueryChangeListener> = Box::new(Box::new(listener));
+        unsafe {
+            let token = CBLQuery_AddChangeListener(self._ref, Some(invoke_query_listener),
+                                                   &*context as *const QueryChangeListener as *mut c_void);
+            return ListenerToken::new(token, Some(context));
+        }
+    }
+
+    /** Starts observing the query's results, returning a `QueryObserver` that receives each
+        new `ResultSet`. This is a channel-based adapter over `add_change_listener`. */
+    pub fn observe(&self) -> QueryObserver {
+        let (sender, receiver) = mpsc::channel();
+        let sender = std::sync::Mutex::new(sender);
+        let token = self.add_change_listener(move |results| {
+            let _ = sender.lock().unwrap().send(results);
+        });
+        QueryObserver{receiver, _token: token}
+    }
+}
+
+unsafe extern "C" fn invoke_query_listener(context: *mut c_void,
+                                           query: *mut CBLQuery,
+                                           token: *mut CBLListenerToken)
+{
+    let listener = &*(context as *const QueryChangeListener);
+    let mut err = CBLError::default();
+    let r = CBLQuery_CopyCurrentResults(query, token, &mut err);
+    if r.is_null() {
+        listener(failure(err));
+    } else {
+        listener(Ok(ResultSet{_ref: r, columns: None}));
+    }
+}
+
+
+/** Receives the changing results of a live query, from `Query::observe`. As an iterator, it
+    blocks until the next results arrive. Observation stops when the observer is dropped. */
+pub struct QueryObserver {
+    receiver: mpsc::Receiver<Result<ResultSet>>,
+    _token: ListenerToken,
+}
+
+impl QueryObserver {
+    /** Returns the next results if they've already arrived, without blocking. */
+    pub fn try_next(&self) -> Option<Result<ResultSet>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /** Waits up to `timeout` for the next results. */
+    pub fn next_timeout(&self, timeout: Duration) -> Option<Result<ResultSet>> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for QueryObserver {
+    type Item = Result<ResultSet>;
+
+    fn next(&mut self) -> Option<Result<ResultSet>> {
+        self.receiver.recv().ok()
+    }
+}
+
+
 impl Drop for Query {
     fn drop(&mut self) {
-        unsafe { release(self._ref); }
+        unsafe {
+            release(self._ref);
+            release(self._db);
+        }
     }
 }
 
 impl Clone for Query {
     fn clone(&self) -> Self {
-        unsafe { Query{_ref: retain(self._ref)} }
+        unsafe {
+            Query{_ref: retain(self._ref), _db: retain(self._db), language: self.language,
+                  source: self.source.clone(), parameter_names: self.parameter_names.clone()}
+        }
     }
 }
 
+// CBLQuery is thread-safe, so a query can be run on another thread.
+unsafe impl Send for Query { }
+
 
 //////// RESULT SET:
 
 
-/** An iterator over the rows resulting from running a query. */
+/** The rows resulting from running a query.
+    There are two ways to step through the rows:
+    - `next_row` returns a `Row` that borrows the result set, so its values can't be used after
+      moving to the next row. This is the fastest way, since nothing is copied.
+    - As an `Iterator`, it yields `OwnedRow`s containing copies of the values, which can be
+      kept, e.g. by `collect()`. Each is a `Result`, since copying the values can fail. */
 pub struct ResultSet {
-    _ref: *mut CBLResultSet
+    _ref: *mut CBLResultSet,
+    columns: Option<Arc<Vec<String>>>,
 }
 
-impl<'r> Iterator for &'r ResultSet {
-    type Item = Row<'r>;
-
-    fn next(&mut self) -> Option<Row<'r>> {
+impl ResultSet {
+    /** Moves to the next row, returning it, or None at the end. */
+    pub fn next_row(&mut self) -> Option<Row<'_>> {
         unsafe {
             if !CBLResultSet_Next(self._ref) {
                 return None;
             }
-            return Some(Row{results: &self})
         }
+        return Some(Row{results: self});
+    }
+
+    /** Returns the first (or next) row, if any, consuming the result set. */
+    pub fn first(mut self) -> Result<Option<OwnedRow>> {
+        self.next().transpose()
+    }
+
+    /** Returns the only row. Fails with `NotFound` if there are no rows, or
+        `InvalidParameter` if there's more than one. */
+    pub fn single(mut self) -> Result<OwnedRow> {
+        let row = self.next().ok_or_else(|| Error::cbl_error(CouchbaseLiteError::NotFound))??;
+        if self.next_row().is_some() {
+            return Err(Error::cbl_error(CouchbaseLiteError::InvalidParameter));
+        }
+        return Ok(row);
+    }
+
+    /** Returns the rows as JSON objects whose keys are the column names. */
+    pub fn to_vec_of_json(mut self) -> Vec<String> {
+        let mut rows = Vec::new();
+        while let Some(row) = self.next_row() {
+            rows.push(row.as_dict().to_json());
+        }
+        return rows;
+    }
+
+    /** Reads all the remaining rows into memory, returning an iterator that knows how many
+        there are (an `ExactSizeIterator`.) */
+    pub fn into_buffered(self) -> Result<std::vec::IntoIter<OwnedRow>> {
+        Ok(self.collect::<Result<Vec<_>>>()?.into_iter())
+    }
+
+    /** Returns the column names. */
+    pub fn column_names(&mut self) -> Arc<Vec<String>> {
+        if self.columns.is_none() {
+            self.columns = Some(Arc::new(unsafe { query_column_names(CBLResultSet_GetQuery(self._ref)) }));
+        }
+        return self.columns.clone().unwrap();
     }
 }
 
+impl Iterator for ResultSet {
+    type Item = Result<OwnedRow>;
+
+    fn next(&mut self) -> Option<Result<OwnedRow>> {
+        let columns = self.column_names();
+        self.next_row().map(|row| row.owned(columns))
+    }
+}
+
+unsafe fn query_column_names(query: *const CBLQuery) -> Vec<String> {
+    (0..CBLQuery_ColumnCount(query))
+        .map(|i| CBLQuery_ColumnName(query, i).as_str().unwrap_or("").to_string())
+        .collect()
+}
+
+// A result set can be handed to another thread, e.g. by a live query's listener.
+unsafe impl Send for ResultSet { }
+
 impl Drop for ResultSet {
     fn drop(&mut self) {
         unsafe { release(self._ref); }
@@ -203,4 +554,126 @@ impl<'r> Row<'r> {
     pub fn as_dict(&self) -> Dict {
         unsafe { Dict{_ref: CBLResultSet_ResultDict(self.results._ref), _owner: PhantomData} }
     }
+
+    /** Returns a copy of the row that doesn't borrow the `ResultSet`. */
+    pub fn to_owned_row(&self) -> Result<OwnedRow> {
+        let columns = unsafe { query_column_names(CBLResultSet_GetQuery(self.results._ref)) };
+        self.owned(Arc::new(columns))
+    }
+
+    pub(crate) fn owned(&self, columns: Arc<Vec<String>>) -> Result<OwnedRow> {
+        // Copy the array, not the dict, since columns can have the same name:
+        let values = Fleece::copy_of(&self.as_array())?;
+        let missing = (0..columns.len()).map(|i| !self.get(i as isize)).collect();
+        Ok(OwnedRow{values, missing, columns})
+    }
+}
+
+
+//////// OWNED ROW:
+
+
+/** A query result row that owns a copy of its values, so it can outlive the `ResultSet`. */
+#[derive(Clone)]
+pub struct OwnedRow {
+    values:  Fleece,                // Array of the column values
+    missing: Vec<bool>,             // Which columns are MISSING (they're null in `values`)
+    columns: Arc<Vec<String>>,
+}
+
+impl OwnedRow {
+    /** Returns the value of a column, given its (zero-based) index. */
+    pub fn get(&self, index: usize) -> Value<'_> {
+        match self.missing.get(index) {
+            Some(false) => self.values.as_array().get(index as u32),
+            _           => Value::UNDEFINED,
+        }
+    }
+
+    /** Returns the value of a column, given its name. If several columns have that name,
+        returns the first. */
+    pub fn get_key(&self, key: &str) -> Value<'_> {
+        match self.columns.iter().position(|column| column == key) {
+            Some(index) => self.get(index),
+            None        => Value::UNDEFINED,
+        }
+    }
+
+    /** Looks up a column by name, optionally followed by a path within it, e.g. `address.city`.
+        See `Value::at_path`. */
+    pub fn get_path(&self, path: &str) -> Result<Value<'_>> {
+        let end = path.find(['.', '[']).unwrap_or(path.len());
+        let value = self.get_key(&path[..end]);
+        match path[end..].trim_start_matches('.') {
+            ""   => Ok(value),
+            rest => value.at_path(rest),
+        }
+    }
+
+    /** Returns the number of columns. */
+    pub fn column_count(&self) -> usize {
+        self.columns.len()
+    }
+
+    /** Returns the name of a column. */
+    pub fn column_name(&self, col: usize) -> Option<&str> {
+        self.columns.get(col).map(|name| name.as_str())
+    }
+
+    /** Returns all of the columns as a Fleece array. MISSING columns are null. */
+    pub fn as_array(&self) -> Array<'_> {
+        self.values.as_array()
+    }
+
+    /** Returns the row as a JSON object whose keys are the column names, leaving out MISSING
+        columns. (Columns with the same name produce duplicate keys.) */
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        for (index, column) in self.columns.iter().enumerate() {
+            let value = self.get(index);
+            if !value {
+                continue;
+            }
+            if json.len() > 1 {
+                json.push(',');
+            }
+            query_builder::write_json_string(column, &mut json);
+            json.push(':');
+            json += &value.to_json();
+        }
+        json.push('}');
+        json
+    }
+}
+
+
+//////// TYPED ROWS:
+
+
+/** A type that can be created from a query result row, such as the row structs declared by
+    the `cbl_query!` macro. */
+pub trait FromRow : Sized {
+    fn from_row(row: &Row<'_>) -> std::result::Result<Self, PropertyError>;
+}
+
+/** An iterator over a `ResultSet` that converts each row to a `FromRow` type. A row that can't
+    be converted (because a column is missing or of the wrong type) produces an error, but
+    iteration can continue past it. */
+pub struct TypedRows<T> {
+    results: ResultSet,
+    _type:   PhantomData<T>,
+}
+
+impl<T: FromRow> TypedRows<T> {
+    pub fn new(results: ResultSet) -> TypedRows<T> {
+        TypedRows{results, _type: PhantomData}
+    }
+}
+
+impl<T: FromRow> Iterator for TypedRows<T> {
+    type Item = std::result::Result<T, PropertyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.results.next_row().map(|row| T::from_row(&row))
+    }
 }
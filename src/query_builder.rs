@@ -0,0 +1,622 @@
+// Couchbase Lite query builder
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+
+use std::fmt;
+use std::fmt::Write;
+
+
+//////// JSON:
+
+
+/** A minimal JSON tree, used to generate query source. */
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn op(name: &str, operands: Vec<Json>) -> Json {
+        let mut items = Vec::with_capacity(operands.len() + 1);
+        items.push(Json::Str(name.to_string()));
+        items.extend(operands);
+        Json::Array(items)
+    }
+
+    pub fn write(&self, out: &mut String) {
+        match self {
+            Json::Null        => out.push_str("null"),
+            Json::Bool(b)     => out.push_str(if *b {"true"} else {"false"}),
+            Json::Int(i)      => { let _ = write!(out, "{}", i); }
+            Json::Float(f)    => {
+                if f.is_finite() {
+                    let _ = write!(out, "{}", f);
+                } else {
+                    out.push_str("null");
+                }
+            }
+            Json::Str(s)      => write_json_string(s, out),
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 { out.push(','); }
+                    item.write(out);
+                }
+                out.push(']');
+            }
+            Json::Object(entries) => {
+                out.push('{');
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 { out.push(','); }
+                    write_json_string(key, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        self.write(&mut out);
+        f.write_str(&out)
+    }
+}
+
+pub(crate) fn write_json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"'  => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c    => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+
+//////// EXPRESSIONS:
+
+
+/** A query expression: a property, parameter, literal value, or an operation on them. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    json: Json,
+}
+
+/** A property of the document, by key path, e.g. `prop("address.city")`. In a query with
+    joins, the path starts with the alias of the data source, e.g. `prop("emp.name")`. */
+pub fn prop(path: &str) -> Expression {
+    Expression::from_json(Json::Array(vec![Json::Str(format!(".{}", path))]))
+}
+
+/** A query parameter, whose value is set by `Query::set_parameters`. */
+pub fn param(name: &str) -> Expression {
+    Expression::from_json(Json::Array(vec![Json::Str(format!("${}", name))]))
+}
+
+/** A variable bound by `func::any`, `func::every` or `func::any_and_every`. */
+pub fn var(name: &str) -> Expression {
+    Expression::from_json(Json::Array(vec![Json::Str(format!("?{}", name))]))
+}
+
+/** A literal value: a number, string or boolean. */
+pub fn val<V: Into<Expression>>(value: V) -> Expression {
+    value.into()
+}
+
+impl Expression {
+    pub(crate) fn from_json(json: Json) -> Expression {
+        Expression{json}
+    }
+
+    fn op(name: &str, operands: Vec<Expression>) -> Expression {
+        Expression::from_json(Json::op(name, operands.into_iter().map(|e| e.json).collect()))
+    }
+
+    fn binary<E: Into<Expression>>(self, name: &str, other: E) -> Expression {
+        Expression::op(name, vec![self, other.into()])
+    }
+
+    /** The literal null value. */
+    pub fn null() -> Expression {
+        Expression::from_json(Json::Null)
+    }
+
+    /** A literal array, whose items are expressions. */
+    pub fn array<I: IntoIterator<Item=Expression>>(items: I) -> Expression {
+        Expression::op("[]", items.into_iter().collect())
+    }
+
+    /** A literal dictionary, whose values are expressions. */
+    pub fn dict<'k, I: IntoIterator<Item=(&'k str, Expression)>>(entries: I) -> Expression {
+        Expression::from_json(Json::Object(entries.into_iter()
+                                                  .map(|(key, value)| (key.to_string(), value.json))
+                                                  .collect()))
+    }
+
+    // Comparison:
+
+    pub fn eq<E: Into<Expression>>(self, other: E) -> Expression   { self.binary("=", other) }
+    pub fn ne<E: Into<Expression>>(self, other: E) -> Expression   { self.binary("!=", other) }
+    pub fn lt<E: Into<Expression>>(self, other: E) -> Expression   { self.binary("<", other) }
+    pub fn le<E: Into<Expression>>(self, other: E) -> Expression   { self.binary("<=", other) }
+    pub fn gt<E: Into<Expression>>(self, other: E) -> Expression   { self.binary(">", other) }
+    pub fn ge<E: Into<Expression>>(self, other: E) -> Expression   { self.binary(">=", other) }
+
+    /** SQL `LIKE` pattern matching, with `%` and `_` wildcards. */
+    pub fn like<E: Into<Expression>>(self, pattern: E) -> Expression { self.binary("LIKE", pattern) }
+
+    /** True if the value is between `low` and `high`, inclusive. */
+    pub fn between<L: Into<Expression>, H: Into<Expression>>(self, low: L, high: H) -> Expression {
+        Expression::op("BETWEEN", vec![self, low.into(), high.into()])
+    }
+
+    /** True if the value is equal to one of the given values. */
+    pub fn in_<I: IntoIterator<Item=Expression>>(self, values: I) -> Expression {
+        Expression::op("IN", vec![self, Expression::array(values)])
+    }
+
+    pub fn is_null(self) -> Expression      { self.binary("IS", Expression::null()) }
+    pub fn is_not_null(self) -> Expression  { self.binary("IS NOT", Expression::null()) }
+    pub fn is_missing(self) -> Expression   { self.binary("IS", Expression::op("MISSING", vec![])) }
+    pub fn is_valued(self) -> Expression    { Expression::op("IS VALUED", vec![self]) }
+
+    // Logic (use `!` for NOT):
+
+    pub fn and<E: Into<Expression>>(self, other: E) -> Expression  { self.binary("AND", other) }
+    pub fn or<E: Into<Expression>>(self, other: E) -> Expression   { self.binary("OR", other) }
+
+    // Arithmetic uses the `+`, `-`, `*`, `/` and `%` operators.
+
+    /** String concatenation. */
+    pub fn concat<E: Into<Expression>>(self, other: E) -> Expression { self.binary("||", other) }
+
+    // Results:
+
+    /** Names the result column of this expression. */
+    pub fn as_(self, alias: &str) -> Expression {
+        Expression::from_json(Json::op("AS", vec![self.json, Json::Str(alias.to_string())]))
+    }
+
+    /** Sorts by this expression in ascending order. */
+    pub fn asc(self) -> SortOrder {
+        SortOrder{json: Json::op("ASC", vec![self.json])}
+    }
+
+    /** Sorts by this expression in descending order. */
+    pub fn desc(self) -> SortOrder {
+        SortOrder{json: Json::op("DESC", vec![self.json])}
+    }
+}
+
+macro_rules! expression_operator {
+    ($($trait:ident :: $method:ident => $op:expr),*) => {$(
+        impl<E: Into<Expression>> std::ops::$trait<E> for Expression {
+            type Output = Expression;
+            fn $method(self, other: E) -> Expression { self.binary($op, other) }
+        }
+    )*}
+}
+
+expression_operator!(Add::add => "+", Sub::sub => "-", Mul::mul => "*",
+                     Div::div => "/", Rem::rem => "%");
+
+impl std::ops::Not for Expression {
+    type Output = Expression;
+    fn not(self) -> Expression { Expression::op("NOT", vec![self]) }
+}
+
+macro_rules! expression_from_integer {
+    ($($t:ty),*) => {$(
+        impl From<$t> for Expression {
+            fn from(n: $t) -> Expression { Expression::from_json(Json::Int(n as i64)) }
+        }
+    )*}
+}
+
+expression_from_integer!(i8, i16, i32, i64, u8, u16, u32);
+
+impl From<f64> for Expression {
+    fn from(n: f64) -> Expression { Expression::from_json(Json::Float(n)) }
+}
+
+impl From<f32> for Expression {
+    fn from(n: f32) -> Expression { Expression::from_json(Json::Float(n as f64)) }
+}
+
+impl From<bool> for Expression {
+    fn from(b: bool) -> Expression { Expression::from_json(Json::Bool(b)) }
+}
+
+impl<'a> From<&'a str> for Expression {
+    fn from(s: &'a str) -> Expression { Expression::from_json(Json::Str(s.to_string())) }
+}
+
+impl From<String> for Expression {
+    fn from(s: String) -> Expression { Expression::from_json(Json::Str(s)) }
+}
+
+
+/** A sort order for `QueryBuilder::order_by`. An `Expression` converts to ascending order. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortOrder {
+    json: Json,
+}
+
+impl From<Expression> for SortOrder {
+    fn from(e: Expression) -> SortOrder { e.asc() }
+}
+
+
+/** Document metadata, usable as expressions. The `_of` variants take the alias of a data
+    source, for queries with joins. */
+pub mod meta {
+    use super::*;
+
+    fn meta(alias: Option<&str>, key: &str) -> Expression {
+        match alias {
+            Some(alias) => prop(&format!("{}.{}", alias, key)),
+            None        => prop(key),
+        }
+    }
+
+    /** The document ID. */
+    pub fn id() -> Expression                         { meta(None, "_id") }
+    pub fn id_of(alias: &str) -> Expression           { meta(Some(alias), "_id") }
+    /** The document's sequence number. */
+    pub fn sequence() -> Expression                   { meta(None, "_sequence") }
+    pub fn sequence_of(alias: &str) -> Expression     { meta(Some(alias), "_sequence") }
+    /** The document's revision ID. */
+    pub fn revision_id() -> Expression                { meta(None, "_revisionID") }
+    pub fn revision_id_of(alias: &str) -> Expression  { meta(Some(alias), "_revisionID") }
+    /** True if the document is deleted. */
+    pub fn deleted() -> Expression                    { meta(None, "_deleted") }
+    pub fn deleted_of(alias: &str) -> Expression      { meta(Some(alias), "_deleted") }
+    /** The document's expiration time, in milliseconds since the Unix epoch. */
+    pub fn expiration() -> Expression                 { meta(None, "_expiration") }
+    pub fn expiration_of(alias: &str) -> Expression   { meta(Some(alias), "_expiration") }
+}
+
+
+/** Functions, including aggregates, array functions and array quantifiers. */
+pub mod func {
+    use super::*;
+
+    /** Calls any function by name, e.g. `func::call("ROUND", vec![prop("price"), val(2)])`. */
+    pub fn call<I: IntoIterator<Item=Expression>>(name: &str, args: I) -> Expression {
+        Expression::op(&format!("{}()", name.to_uppercase()), args.into_iter().collect())
+    }
+
+    pub fn count(e: Expression) -> Expression   { call("COUNT", vec![e]) }
+    pub fn sum(e: Expression) -> Expression     { call("SUM", vec![e]) }
+    pub fn avg(e: Expression) -> Expression     { call("AVG", vec![e]) }
+    pub fn min(e: Expression) -> Expression     { call("MIN", vec![e]) }
+    pub fn max(e: Expression) -> Expression     { call("MAX", vec![e]) }
+
+    pub fn lower(e: Expression) -> Expression   { call("LOWER", vec![e]) }
+    pub fn upper(e: Expression) -> Expression   { call("UPPER", vec![e]) }
+    pub fn length(e: Expression) -> Expression  { call("LENGTH", vec![e]) }
+
+    /** True if the array contains the value. */
+    pub fn array_contains<E: Into<Expression>>(array: Expression, value: E) -> Expression {
+        call("ARRAY_CONTAINS", vec![array, value.into()])
+    }
+
+    /** The number of items in the array. */
+    pub fn array_length(array: Expression) -> Expression {
+        call("ARRAY_LENGTH", vec![array])
+    }
+
+    /** The number of non-null items in the array. */
+    pub fn array_count(array: Expression) -> Expression {
+        call("ARRAY_COUNT", vec![array])
+    }
+
+    fn quantifier(name: &str, variable: &str, array: Expression, satisfies: Expression) -> Expression {
+        Expression::from_json(Json::op(name, vec![Json::Str(variable.to_string()),
+                                                  array.json, satisfies.json]))
+    }
+
+    /** True if any item of the array satisfies the condition, in which the item is
+        `var(variable)`. */
+    pub fn any(variable: &str, array: Expression, satisfies: Expression) -> Expression {
+        quantifier("ANY", variable, array, satisfies)
+    }
+
+    /** True if every item of the array satisfies the condition (or the array is empty.) */
+    pub fn every(variable: &str, array: Expression, satisfies: Expression) -> Expression {
+        quantifier("EVERY", variable, array, satisfies)
+    }
+
+    /** True if the array is non-empty and every item satisfies the condition. */
+    pub fn any_and_every(variable: &str, array: Expression, satisfies: Expression) -> Expression {
+        quantifier("ANY AND EVERY", variable, array, satisfies)
+    }
+
+    /** The dictionary returned by a registered predictive model, given an input dictionary,
+        e.g. `func::prediction("classifier", Expression::dict(vec![("text", prop("body"))]))`.
+        Predictive models are only available in the Enterprise Edition. */
+    pub fn prediction(model: &str, input: Expression) -> Expression {
+        Expression::from_json(Json::op("PREDICTION()", vec![Json::Str(model.to_string()), input.json]))
+    }
+
+    /** A property of the dictionary returned by a predictive model, by key path. */
+    pub fn prediction_property(model: &str, input: Expression, path: &str) -> Expression {
+        Expression::from_json(Json::op("PREDICTION()", vec![Json::Str(model.to_string()), input.json,
+                                                            Json::Str(format!(".{}", path))]))
+    }
+}
+
+
+/** Full-text search functions, which use a full-text index. */
+pub mod full_text {
+    use super::*;
+
+    /** True if the document matches the search text in the full-text index. The text is
+        usually a string, or a `param` to bind it when the query runs. */
+    pub fn matches<E: Into<Expression>>(index: &str, text: E) -> Expression {
+        Expression::from_json(Json::op("MATCH()", vec![Json::Str(index.to_string()),
+                                                       text.into().json]))
+    }
+
+    /** The relevance of the match, for sorting; only valid in a query that uses `matches`. */
+    pub fn rank(index: &str) -> Expression {
+        Expression::from_json(Json::op("RANK()", vec![Json::Str(index.to_string())]))
+    }
+}
+
+
+/** Vector search functions, which use a vector index. */
+#[cfg(feature = "vector-search")]
+pub mod vector_search {
+    use super::*;
+
+    /** The approximate distance between the indexed vector expression (which must match the
+        index's expression) and a target vector, for sorting by nearness, e.g.
+        `approx_distance(prop("embedding"), param("target"))`; bind the target with
+        `Query::bind`, as a `Vec<f32>`. */
+    pub fn approx_distance<E: Into<Expression>>(vector: Expression, target: E) -> Expression {
+        func::call("APPROX_VECTOR_DISTANCE", vec![vector, target.into()])
+    }
+
+    /** Like `approx_distance`, with the index's metric, and optionally the number of buckets to
+        search; if it's None, the index's default is used. More buckets are slower but more
+        accurate. */
+    pub fn approx_distance_with<E: Into<Expression>>(vector: Expression, target: E,
+                                                     metric: DistanceMetric, probes: Option<u32>) -> Expression {
+        let mut args = vec![vector, target.into(), val(metric.name())];
+        if let Some(probes) = probes {
+            args.push(val(probes as i64));
+        }
+        func::call("APPROX_VECTOR_DISTANCE", args)
+    }
+}
+
+
+//////// QUERY BUILDER:
+
+
+/** Join types for `Join`. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinType {
+    Inner,
+    LeftOuter,
+    Cross,
+}
+
+/** A join of another data source, in the same database, to a query. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    alias:      String,
+    join_type:  JoinType,
+    on:         Option<Expression>,
+}
+
+impl Join {
+    pub fn inner(alias: &str) -> Join        { Join::new(alias, JoinType::Inner) }
+    pub fn left_outer(alias: &str) -> Join   { Join::new(alias, JoinType::LeftOuter) }
+    pub fn cross(alias: &str) -> Join        { Join::new(alias, JoinType::Cross) }
+
+    fn new(alias: &str, join_type: JoinType) -> Join {
+        Join{alias: alias.to_string(), join_type, on: None}
+    }
+
+    /** Sets the join condition. Cross joins don't have one. */
+    pub fn on(mut self, condition: Expression) -> Join {
+        self.on = Some(condition);
+        self
+    }
+
+    fn to_json(&self) -> Json {
+        let mut entries = vec![("AS".to_string(), Json::Str(self.alias.clone()))];
+        let join = match self.join_type {
+            JoinType::Inner     => "INNER",
+            JoinType::LeftOuter => "LEFT OUTER",
+            JoinType::Cross     => "CROSS",
+        };
+        entries.push(("JOIN".to_string(), Json::Str(join.to_string())));
+        if let Some(on) = &self.on {
+            entries.push(("ON".to_string(), on.json.clone()));
+        }
+        Json::Object(entries)
+    }
+}
+
+
+/** The `SELECT` clause of a query being built. Call `from` to continue building. */
+#[derive(Debug, Clone)]
+pub struct Select {
+    what:       Vec<Expression>,
+    distinct:   bool,
+}
+
+/** A query being built, which generates the JSON query schema:
+    <https://github.com/couchbase/couchbase-lite-core/wiki/JSON-Query-Schema>
+    Create one with `QueryBuilder::select`, then `from`:
+    ```ignore
+    let query = QueryBuilder::select(vec![prop("name"), meta::id()])
+                    .from(&db)
+                    .where_(prop("age").gt(param("min")))
+                    .order_by(vec![prop("name").asc()])
+                    .limit(10)
+                    .build()?;
+    ``` */
+#[derive(Clone)]
+pub struct QueryBuilder<'db> {
+    db:         &'db Database,
+    select:     Select,
+    alias:      Option<String>,
+    joins:      Vec<Join>,
+    where_:     Option<Expression>,
+    group_by:   Vec<Expression>,
+    having:     Option<Expression>,
+    order_by:   Vec<SortOrder>,
+    limit:      Option<Expression>,
+    offset:     Option<Expression>,
+}
+
+impl<'db> QueryBuilder<'db> {
+    /** Starts a query returning the given result columns. */
+    pub fn select<I: IntoIterator<Item=Expression>>(what: I) -> Select {
+        Select{what: what.into_iter().collect(), distinct: false}
+    }
+
+    /** Starts a query returning the given result columns, without duplicate rows. */
+    pub fn select_distinct<I: IntoIterator<Item=Expression>>(what: I) -> Select {
+        Select{what: what.into_iter().collect(), distinct: true}
+    }
+
+    /** Adds a join with another data source. */
+    pub fn join(mut self, join: Join) -> Self {
+        self.joins.push(join);
+        self
+    }
+
+    /** Sets the `WHERE` condition. */
+    pub fn where_(mut self, condition: Expression) -> Self {
+        self.where_ = Some(condition);
+        self
+    }
+
+    /** Sets the `GROUP BY` expressions. */
+    pub fn group_by<I: IntoIterator<Item=Expression>>(mut self, by: I) -> Self {
+        self.group_by = by.into_iter().collect();
+        self
+    }
+
+    /** Sets the `HAVING` condition, which filters the groups. */
+    pub fn having(mut self, condition: Expression) -> Self {
+        self.having = Some(condition);
+        self
+    }
+
+    /** Sets the sort order. */
+    pub fn order_by<O: Into<SortOrder>, I: IntoIterator<Item=O>>(mut self, by: I) -> Self {
+        self.order_by = by.into_iter().map(|o| o.into()).collect();
+        self
+    }
+
+    /** Sets the maximum number of rows; a number, or an expression such as a parameter. */
+    pub fn limit<E: Into<Expression>>(mut self, limit: E) -> Self {
+        self.limit = Some(limit.into());
+        self
+    }
+
+    /** Sets the number of rows to skip; a number, or an expression such as a parameter. */
+    pub fn offset<E: Into<Expression>>(mut self, offset: E) -> Self {
+        self.offset = Some(offset.into());
+        self
+    }
+
+    /** Returns the query in the JSON query schema. */
+    pub fn to_json(&self) -> String {
+        let mut entries = Vec::new();
+        let what = self.select.what.iter().map(|e| e.json.clone()).collect();
+        entries.push(("WHAT".to_string(), Json::Array(what)));
+        if self.select.distinct {
+            entries.push(("DISTINCT".to_string(), Json::Bool(true)));
+        }
+        if self.alias.is_some() || !self.joins.is_empty() {
+            // The first entry is always the main data source, even if it has no alias:
+            let mut main_source = Vec::new();
+            if let Some(alias) = &self.alias {
+                main_source.push(("AS".to_string(), Json::Str(alias.clone())));
+            }
+            let mut from = vec![Json::Object(main_source)];
+            from.extend(self.joins.iter().map(|j| j.to_json()));
+            entries.push(("FROM".to_string(), Json::Array(from)));
+        }
+        if let Some(condition) = &self.where_ {
+            entries.push(("WHERE".to_string(), condition.json.clone()));
+        }
+        if !self.group_by.is_empty() {
+            let group_by = self.group_by.iter().map(|e| e.json.clone()).collect();
+            entries.push(("GROUP_BY".to_string(), Json::Array(group_by)));
+        }
+        if let Some(condition) = &self.having {
+            entries.push(("HAVING".to_string(), condition.json.clone()));
+        }
+        if !self.order_by.is_empty() {
+            let order_by = self.order_by.iter().map(|o| o.json.clone()).collect();
+            entries.push(("ORDER_BY".to_string(), Json::Array(order_by)));
+        }
+        if let Some(limit) = &self.limit {
+            entries.push(("LIMIT".to_string(), limit.json.clone()));
+        }
+        if let Some(offset) = &self.offset {
+            entries.push(("OFFSET".to_string(), offset.json.clone()));
+        }
+        Json::Object(entries).to_string()
+    }
+
+    /** Compiles the query. */
+    pub fn build(&self) -> Result<Query> {
+        Query::new(self.db, QueryLanguage::JSON, &self.to_json())
+    }
+}
+
+impl Select {
+    /** Queries the documents of a database. */
+    pub fn from(self, db: &Database) -> QueryBuilder<'_> {
+        QueryBuilder{db, select: self, alias: None, joins: Vec::new(), where_: None,
+                     group_by: Vec::new(), having: None, order_by: Vec::new(),
+                     limit: None, offset: None}
+    }
+
+    /** Queries the documents of a database, giving them an alias to use in joins. */
+    pub fn from_as<'db>(self, db: &'db Database, alias: &str) -> QueryBuilder<'db> {
+        let mut builder = self.from(db);
+        builder.alias = Some(alias.to_string());
+        builder
+    }
+}
@@ -0,0 +1,143 @@
+// Couchbase Lite prepared-query cache
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+
+use std::collections::HashMap;
+
+
+/** The default number of compiled queries a database's cache holds. */
+pub const DEFAULT_QUERY_CACHE_CAPACITY: usize = 64;
+
+
+/** Statistics about a database's query cache. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryCacheStats {
+    pub hits:      u64,     // Lookups that found a compiled query
+    pub misses:    u64,     // Lookups that had to compile the query
+    pub evictions: u64,     // Queries removed to make room for newer ones
+    pub size:      usize,   // Number of queries in the cache
+    pub capacity:  usize,   // Maximum number of queries in the cache
+}
+
+
+/** A least-recently-used cache of compiled queries, keyed by their source. */
+pub(crate) struct QueryCache {
+    entries: HashMap<(QueryLanguage, String), (Query, u64)>,    // Query and time last used
+    clock:   u64,
+    stats:   QueryCacheStats,
+}
+
+impl Default for QueryCache {
+    fn default() -> QueryCache {
+        QueryCache {
+            entries: HashMap::new(),
+            clock:   0,
+            stats:   QueryCacheStats{capacity: DEFAULT_QUERY_CACHE_CAPACITY, ..Default::default()},
+        }
+    }
+}
+
+impl QueryCache {
+    fn get(&mut self, language: QueryLanguage, source: &str) -> Option<Query> {
+        self.clock += 1;
+        // (Looking up a tuple key requires an owned String.)
+        match self.entries.get_mut(&(language, source.to_string())) {
+            Some(&mut (ref query, ref mut last_used)) => {
+                *last_used = self.clock;
+                self.stats.hits += 1;
+                Some(query.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, language: QueryLanguage, source: &str, query: Query) {
+        if self.stats.capacity == 0 {
+            return;
+        }
+        self.trim(self.stats.capacity - 1);
+        self.entries.insert((language, source.to_string()), (query, self.clock));
+    }
+
+    // Evicts the least recently used queries until there are at most `size`.
+    fn trim(&mut self, size: usize) {
+        while self.entries.len() > size {
+            let oldest = self.entries.iter()
+                             .min_by_key(|(_, &(_, last_used))| last_used)
+                             .map(|(key, _)| key.clone())
+                             .unwrap();
+            self.entries.remove(&oldest);
+            self.stats.evictions += 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+
+impl Database {
+    /** Returns a compiled query for the given source, compiling it only if it isn't already in
+        the database's query cache. Queries are cached by their exact source text.
+
+        A cached `Query` is shared with other callers of the same source, so a query with
+        `$parameters` isn't cached: their values would be shared too. Each call compiles a new
+        one, with no parameters bound.
+
+        The cache is cleared when an index is created or deleted, since that can change the best
+        plan for a query, and when the database is closed. The cache belongs to this `Database`
+        instance, though: if an index is created or deleted through another `Database` opened on
+        the same file, call `clear_query_cache` so the cached queries are recompiled. */
+    pub fn cached_query(&self, language: QueryLanguage, source: &str) -> Result<Query> {
+        if let Some(query) = self.query_cache().get(language, source) {
+            return Ok(query);
+        }
+        let query = Query::new(self, language, source)?;
+        if query.parameter_names().is_empty() {
+            self.query_cache().insert(language, source, query.clone());
+        }
+        Ok(query)
+    }
+
+    /** Sets the maximum number of compiled queries the query cache holds, evicting the least
+        recently used ones if necessary. A capacity of 0 disables the cache. */
+    pub fn set_query_cache_capacity(&self, capacity: usize) {
+        let mut cache = self.query_cache();
+        cache.stats.capacity = capacity;
+        cache.trim(capacity);
+    }
+
+    /** Returns the query cache's hit/miss statistics and size. */
+    pub fn query_cache_stats(&self) -> QueryCacheStats {
+        let cache = self.query_cache();
+        QueryCacheStats{size: cache.entries.len(), ..cache.stats}
+    }
+
+    /** Removes all queries from the query cache. */
+    pub fn clear_query_cache(&self) {
+        self.query_cache().clear();
+    }
+
+    fn query_cache(&self) -> std::sync::MutexGuard<'_, QueryCache> {
+        self._extras.query_cache.lock().unwrap()
+    }
+}
@@ -0,0 +1,244 @@
+// Couchbase Lite query plans
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+
+
+/** A query's execution plan, parsed from the output of `Query::explain`. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPlan {
+    pub sql:     String,            // The SQLite translation of the query
+    pub steps:   Vec<QueryPlanStep>,// SQLite's query plan
+    pub indexes: Vec<String>,       // Names of the indexes the plan uses
+    pub raw:     String,            // The complete output of `explain`
+}
+
+/** One step of a query plan, e.g. `SEARCH TABLE kv_default AS _doc USING INDEX byName (<expr>=?)`. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPlanStep {
+    pub id:     i64,
+    pub parent: i64,
+    pub detail: String,
+}
+
+/** A step that scans every row of a table, because no index applies. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct FullScan {
+    pub table:      String,         // The table, or its alias if that's all the plan names
+    pub predicates: Vec<String>,    // Conditions of the `WHERE` clause on this table (SQL)
+}
+
+
+impl Query {
+    /** Returns the query's execution plan, in parsed form. See `explain`. */
+    pub fn plan(&self) -> QueryPlan {
+        QueryPlan::parse(&self.explain())
+    }
+}
+
+
+impl QueryPlan {
+    /** Parses the output of `Query::explain`, which consists of the SQL, a blank line, and the
+        plan steps as `id|parent|notused|detail` lines, possibly followed by the original query. */
+    pub fn parse(explain: &str) -> QueryPlan {
+        let mut sql = Vec::new();
+        let mut steps = Vec::new();
+        let mut in_sql = true;
+        for line in explain.lines() {
+            if in_sql {
+                if line.trim().is_empty() {
+                    in_sql = sql.is_empty();
+                } else if let Some(step) = QueryPlanStep::parse(line) {
+                    in_sql = false;
+                    steps.push(step);
+                } else {
+                    sql.push(line.trim());
+                }
+            } else if let Some(step) = QueryPlanStep::parse(line) {
+                steps.push(step);
+            }
+        }
+
+        let mut indexes: Vec<String> = Vec::new();
+        for step in &steps {
+            if let Some(index) = step.index() {
+                if !indexes.contains(&index) {
+                    indexes.push(index);
+                }
+            }
+        }
+        QueryPlan{sql: sql.join(" "), steps, indexes, raw: explain.to_string()}
+    }
+
+    /** Returns true if the plan uses the named index. */
+    pub fn uses_index(&self, name: &str) -> bool {
+        self.indexes.iter().any(|index| index == name)
+    }
+
+    /** Returns the steps that scan an entire table without using an index, each with the
+        `WHERE` conditions on that table that an index could have been used for. */
+    pub fn full_scans(&self) -> Vec<FullScan> {
+        let conditions = where_conditions(&self.sql);
+        self.steps.iter().filter_map(|step| {
+            let (table, alias) = step.scanned_table()?;
+            let prefix = format!("{}.", alias.as_ref().unwrap_or(&table));
+            let predicates = conditions.iter()
+                .filter(|c| c.contains(&prefix) && !c.contains(".flags"))
+                .cloned()
+                .collect();
+            Some(FullScan{table, predicates})
+        }).collect()
+    }
+}
+
+
+impl QueryPlanStep {
+    fn parse(line: &str) -> Option<QueryPlanStep> {
+        let mut fields = line.splitn(4, '|');
+        let id = fields.next()?.trim().parse().ok()?;
+        let parent = fields.next()?.trim().parse().ok()?;
+        let _notused = fields.next()?;
+        let detail = fields.next()?.trim().to_string();
+        Some(QueryPlanStep{id, parent, detail})
+    }
+
+    // The name of the index used by this step, if any.
+    fn index(&self) -> Option<String> {
+        let words: Vec<&str> = self.detail.split_whitespace().collect();
+        if let Some(i) = words.iter().position(|w| *w == "INDEX") {
+            if i > 0 && (words[i-1] == "USING" || words[i-1] == "COVERING") {
+                return words.get(i+1).map(|name| name.to_string());
+            }
+        }
+        if self.detail.contains("VIRTUAL TABLE") {
+            // A full-text index: "SCAN TABLE kv_default::byText AS fts1 VIRTUAL TABLE INDEX ..."
+            let table = words.iter().position(|w| *w == "TABLE").and_then(|i| words.get(i+1))?;
+            return table.rsplit(':').next().map(|name| name.to_string());
+        }
+        None
+    }
+
+    // If this step is a full table scan, the table name and alias.
+    fn scanned_table(&self) -> Option<(String, Option<String>)> {
+        let words: Vec<&str> = self.detail.split_whitespace().collect();
+        if words.first() != Some(&"SCAN") || self.detail.contains("USING")
+                || self.detail.contains("VIRTUAL TABLE") || words.get(1) == Some(&"CONSTANT") {
+            return None;
+        }
+        let mut rest = &words[1..];
+        if rest.first() == Some(&"TABLE") {
+            rest = &rest[1..];
+        }
+        let table = rest.first()?.to_string();
+        let alias = if rest.get(1) == Some(&"AS") { rest.get(2).map(|a| a.to_string()) }
+                    else { None };
+        Some((table, alias))
+    }
+}
+
+
+// Splits the top-level `WHERE` clause of a SQL statement into its `AND`ed conditions.
+fn where_conditions(sql: &str) -> Vec<String> {
+    let start = match sql.find(" WHERE ") {
+        Some(i) => i + " WHERE ".len(),
+        None    => return Vec::new(),
+    };
+    let mut clause = &sql[start..];
+    for end in &[" GROUP BY ", " ORDER BY ", " LIMIT ", " OFFSET "] {
+        if let Some(i) = clause.find(end) {
+            clause = &clause[..i];
+        }
+    }
+
+    let mut conditions = Vec::new();
+    split_conjunction(clause, &mut conditions);
+    conditions
+}
+
+// Adds the terms of an `AND` expression to `conditions`, looking inside parentheses, since
+// LiteCore writes the WHERE clause as `(<query's condition>) AND (<not deleted>)`.
+fn split_conjunction(expr: &str, conditions: &mut Vec<String>) {
+    let expr = expr.trim();
+    if let Some(inner) = strip_parens(expr) {
+        return split_conjunction(inner, conditions);
+    }
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut begin = 0;
+    let mut terms = Vec::new();
+    let bytes = expr.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' => in_string = !in_string,
+            b'(' if !in_string => depth += 1,
+            b')' if !in_string => depth -= 1,
+            b' ' if !in_string && depth == 0 && expr[i..].starts_with(" AND ") => {
+                terms.push(&expr[begin..i]);
+                i += " AND ".len();
+                begin = i;
+                continue;
+            }
+            _ => { }
+        }
+        i += 1;
+    }
+    if terms.is_empty() {
+        if !expr.is_empty() {
+            conditions.push(expr.to_string());
+        }
+        return;
+    }
+    terms.push(&expr[begin..]);
+    for term in terms {
+        split_conjunction(term, conditions);
+    }
+}
+
+// Returns the inside of an expression that's entirely enclosed in one pair of parentheses.
+fn strip_parens(expr: &str) -> Option<&str> {
+    if !expr.starts_with('(') || !expr.ends_with(')') {
+        return None;
+    }
+    let mut depth = 0;
+    let mut in_string = false;
+    for (i, b) in expr.bytes().enumerate() {
+        match b {
+            b'\'' => in_string = !in_string,
+            b'(' if !in_string => depth += 1,
+            b')' if !in_string => {
+                depth -= 1;
+                if depth == 0 && i < expr.len() - 1 {
+                    return None;
+                }
+            }
+            _ => { }
+        }
+    }
+    Some(&expr[1..expr.len() - 1])
+}
+
+
+/** Panics unless the query's plan uses the named index. Intended for tests, to catch queries
+    that regress to scanning a whole table. */
+pub fn assert_uses_index(query: &Query, index: &str) {
+    let plan = query.plan();
+    if !plan.uses_index(index) {
+        panic!("query doesn't use index `{}`; full scans: {:?}\n{}",
+               index, plan.full_scans(), plan.raw);
+    }
+}
@@ -0,0 +1,110 @@
+// Couchbase Lite query result streaming
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+
+/** The default number of rows a `RowStream` buffers ahead of its reader. */
+pub const DEFAULT_ROW_STREAM_BUFFER: usize = 256;
+
+
+impl Query {
+    /** Runs the query and sends each row, as an `OwnedRow`, to a channel, returning the number
+        of rows sent. If the channel is bounded, this blocks while it's full, so the rows are
+        produced only as fast as the receiver consumes them. Stops early (without an error) if the
+        receiver hangs up, or with an error if a row can't be copied.
+
+        Since rows are owned copies, the receiver can be on another thread; or call this on a
+        worker thread, with a clone of the query. See also `execute_in_background`. */
+    pub fn execute_to_channel(&self, sender: mpsc::SyncSender<OwnedRow>) -> Result<u64> {
+        self.send_rows(&sender, |row, columns| row.owned(columns.clone()))
+    }
+
+    /** Runs the query on a new worker thread, returning an iterator over the rows as they arrive.
+        At most `buffer` rows are read ahead of the iterator. If the query fails, the iterator
+        returns the error and then ends. Dropping the iterator stops the worker.
+        The worker runs a separate copy of the query, compiled with its current parameters, so
+        changing them afterwards has no effect. */
+    pub fn execute_in_background(&self, buffer: usize) -> RowStream<OwnedRow> {
+        self.stream_rows(buffer, |row, columns| row.owned(columns.clone()))
+    }
+
+    // Runs the query, converting each row and sending it to `sender`, until the results end or
+    // the receiver hangs up. Stops with an error if a row can't be converted.
+    pub(crate) fn send_rows<T, F>(&self, sender: &mpsc::SyncSender<T>, mut convert: F) -> Result<u64>
+        where F: FnMut(&Row, &Arc<Vec<String>>) -> Result<T>
+    {
+        let mut results = self.execute()?;
+        let columns = results.column_names();
+        let mut count = 0;
+        while let Some(row) = results.next_row() {
+            if sender.send(convert(&row, &columns)?).is_err() {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    // Runs `send_rows` on a worker thread with a snapshot of the query. A row that can't be
+    // converted is sent as an error; an error running the query is sent as the last item.
+    pub(crate) fn stream_rows<T, F>(&self, buffer: usize, mut convert: F) -> RowStream<T>
+        where T: Send + 'static,
+              F: FnMut(&Row, &Arc<Vec<String>>) -> Result<T> + Send + 'static
+    {
+        let query = self.snapshot();
+        let (sender, receiver) = mpsc::sync_channel(buffer);
+        let worker = thread::spawn(move || {
+            let result = query.and_then(|query| {
+                query.send_rows(&sender, |row, columns| Ok(convert(row, columns)))
+            });
+            if let Err(error) = result {
+                let _ = sender.send(Err(error));
+            }
+        });
+        RowStream{receiver: Some(receiver), worker: Some(worker)}
+    }
+}
+
+
+/** An iterator over query results produced on a worker thread, returned by
+    `Query::execute_in_background`. */
+pub struct RowStream<T> {
+    receiver: Option<mpsc::Receiver<Result<T>>>,
+    worker:   Option<thread::JoinHandle<()>>,
+}
+
+impl<T> Iterator for RowStream<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        self.receiver.as_ref().and_then(|receiver| receiver.recv().ok())
+    }
+}
+
+impl<T> Drop for RowStream<T> {
+    fn drop(&mut self) {
+        // Hang up first, so a worker blocked on a full channel wakes up and exits:
+        self.receiver = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
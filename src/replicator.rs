@@ -20,6 +20,8 @@ use super::c_api::*;
 
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
 
 
 // WARNING: THIS API IS UNIMPLEMENTED SO FAR
@@ -98,12 +100,19 @@ pub struct ReplicatorConfiguration<'c> {
 
 /** A background task that syncs a \ref Database with a remote server or peer. */
 pub struct Replicator {
-    _ref: *mut CBLReplicator
+    _ref: *mut CBLReplicator,
+    _context: Box<ReplicatorContext>   // Passed to the C callbacks; must outlive `_ref`
 }
 
 impl Replicator {
-    /** Creates a replicator with the given configuration. */
-    pub fn new(_config: ReplicatorConfiguration) -> Result<Replicator> {
+    /** Creates a replicator with the given configuration.
+
+        Pulled documents are checked by the database's validators (see `register_validator`)
+        before `pull_filter` is called; one that fails is rejected, and the failure is logged. */
+    pub fn new(config: ReplicatorConfiguration) -> Result<Replicator> {
+        let _context = Box::new(ReplicatorContext{database:    config.database.clone(),
+                                                  pull_filter: config.pull_filter});
+        let _pull_filter : CBLReplicationFilter = Some(c_pull_filter);
         todo!()
     }
 
@@ -200,6 +209,13 @@ pub struct ReplicatedDocument<'d> {
     pub error:  Result<()>                  // Error, if document failed to replicate
 }
 
+impl<'d> ReplicatedDocument<'d> {
+    /** True if the replicated revision is a deletion, according to its revision flags. */
+    pub fn is_deleted(&self) -> bool {
+        self.flags & DELETED != 0
+    }
+}
+
 /** Direction of document transfer. */
 #[derive(Debug)]
 pub enum Direction {Pulled, Pushed }
@@ -240,3 +256,36 @@ impl Replicator {
         todo!()
     }
 }
+
+
+//======== INTERNALS
+
+
+/** The state the C replicator's callbacks need, pointed to by its configuration's `context`. */
+struct ReplicatorContext {
+    database:    Database,
+    pull_filter: ReplicationFilter,
+}
+
+/** The C configuration's `pullFilter`. Rejects a pulled document that fails validation, then
+    defers to the configuration's `pull_filter`. */
+unsafe extern "C" fn c_pull_filter(context: *mut c_void,
+                                   document: *mut CBLDocument,
+                                   flags: CBLDocumentFlags) -> bool {
+    let context = &*(context as *const ReplicatorContext);
+    let document = Document::adopt(retain(document), context.database._ref, flags);
+    let is_deleted = document.is_deleted();
+    // A panic must not unwind into C; a filter that panics rejects the document.
+    let accepted = panic::catch_unwind(AssertUnwindSafe(|| {
+        // A deletion has no properties to validate:
+        if !is_deleted {
+            if let Err(error) = context.database.validate_document(&document) {
+                logging::write(logging::Domain::Replicator, logging::Level::Warning,
+                               &format!("Rejected pulled document: {}", error));
+                return false;
+            }
+        }
+        (context.pull_filter)(&document, is_deleted)
+    }));
+    return accepted.unwrap_or(false);
+}
@@ -0,0 +1,247 @@
+// Couchbase Lite full-text search
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+
+use std::ops::Range;
+
+
+/** How `Database::search` interprets the search text. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchMode {
+    AllWords,       // Every word must occur (in any order)
+    AnyWord,        // At least one word must occur
+    Phrase,         // The words must occur together, in order
+    Prefix,         // Every word must occur as the start of a word, e.g. "dat" matches "database"
+    Boolean,        // The text is a full-text query: words, "phrases", prefix*, AND, OR, NOT, (...)
+}
+
+
+/** Options for `Database::search`.
+    (Stemming and stop-words depend on the language the index was created with; see
+    `FullTextIndexOptions`.) */
+#[derive(Debug, Clone)]
+pub struct SearchOptions {
+    pub mode:          SearchMode,
+    pub limit:         Option<usize>,       // Maximum number of hits
+    pub text_path:     Option<String>,      // Property path of the indexed text; if given, each
+                                            // hit has a snippet and match offsets
+    pub snippet_words: usize,               // Number of words in a snippet
+    pub highlight:     (String, String),    // Strings inserted before and after each match
+}
+
+impl Default for SearchOptions {
+    fn default() -> SearchOptions {
+        SearchOptions {
+            mode:          SearchMode::AllWords,
+            limit:         None,
+            text_path:     None,
+            snippet_words: 12,
+            highlight:     ("<b>".to_string(), "</b>".to_string()),
+        }
+    }
+}
+
+
+/** A document matching a full-text search. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub id:      String,                // Document ID
+    pub rank:    f64,                   // Relevance; higher is better
+    pub text:    Option<String>,        // The text at `SearchOptions::text_path`
+    pub matches: Vec<Range<usize>>,     // Byte ranges of the matched words in `text`
+    pub snippet: Option<String>,        // Excerpt of `text` around the first match, highlighted
+}
+
+/** The results of `Database::search`, best match first. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResults {
+    pub match_expression: String,       // The full-text query the search text was translated to
+    pub hits:             Vec<SearchHit>,
+}
+
+impl SearchResults {
+    pub fn len(&self) -> usize          { self.hits.len() }
+    pub fn is_empty(&self) -> bool      { self.hits.is_empty() }
+
+    /** The IDs of the matching documents, best match first. */
+    pub fn ids(&self) -> Vec<&str> {
+        self.hits.iter().map(|hit| hit.id.as_str()).collect()
+    }
+}
+
+impl IntoIterator for SearchResults {
+    type Item = SearchHit;
+    type IntoIter = std::vec::IntoIter<SearchHit>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.hits.into_iter()
+    }
+}
+
+
+impl Database {
+    /** Searches a full-text index (see `create_full_text_index`), returning the matching
+        documents ranked by relevance.
+
+        Snippets and match offsets are computed from the text at `options.text_path`, by finding
+        the search words in it; words that only matched through stemming aren't highlighted. */
+    pub fn search(&self, index: &str, text: &str, options: &SearchOptions) -> Result<SearchResults> {
+        let match_expression = match_expression(text, options.mode);
+        let mut results = SearchResults{match_expression, hits: Vec::new()};
+        if results.match_expression.is_empty() {
+            return Ok(results);
+        }
+
+        let mut what = vec![meta::id(), full_text::rank(index)];
+        if let Some(path) = &options.text_path {
+            what.push(prop(path));
+        }
+        let mut builder = QueryBuilder::select(what)
+                              .from(self)
+                              .where_(full_text::matches(index, param("text")))
+                              .order_by(vec![full_text::rank(index).desc()]);
+        if let Some(limit) = options.limit {
+            builder = builder.limit(limit as i64);
+        }
+        let query = builder.build()?;
+        let mut parameters = MutableDict::new();
+        parameters.at("text").put_string(&results.match_expression);
+        query.set_parameters(parameters);
+
+        let terms = search_terms(&results.match_expression);
+        for row in query.execute()? {
+            let row = row?;
+            let mut hit = SearchHit {
+                id:      row.get(0).as_string().unwrap_or("").to_string(),
+                rank:    row.get(1).as_f64_or_0(),
+                text:    row.get(2).as_string().map(str::to_string),
+                matches: Vec::new(),
+                snippet: None,
+            };
+            if let Some(text) = &hit.text {
+                let words = words_of(text);
+                hit.matches = words.iter().filter(|w| terms.iter().any(|t| t.matches(&text[(*w).clone()])))
+                                   .cloned()
+                                   .collect();
+                hit.snippet = Some(snippet(text, &words, &hit.matches, options));
+            }
+            results.hits.push(hit);
+        }
+        Ok(results)
+    }
+}
+
+
+// Translates search text into a full-text query, according to the mode. Each word is quoted,
+// so that words like AND, OR, NOT and NEAR in the text are searched for, not taken as operators.
+fn match_expression(text: &str, mode: SearchMode) -> String {
+    if mode == SearchMode::Boolean {
+        return text.trim().to_string();
+    }
+    let words: Vec<&str> = words_of(text).into_iter().map(|range| &text[range]).collect();
+    let quoted = |suffix: &str| -> Vec<String> {
+        words.iter().map(|w| format!("\"{}{}\"", w, suffix)).collect()
+    };
+    match mode {
+        SearchMode::AllWords => quoted("").join(" "),
+        SearchMode::AnyWord  => quoted("").join(" OR "),
+        SearchMode::Phrase   => if words.is_empty() { String::new() }
+                                else { format!("\"{}\"", words.join(" ")) },
+        // A quoted prefix search is written `"word*"`:
+        SearchMode::Prefix   => quoted("*").join(" "),
+        SearchMode::Boolean  => unreachable!(),
+    }
+}
+
+// A word of a full-text query, for highlighting.
+struct SearchTerm {
+    word:   String,     // Lowercased
+    prefix: bool,       // Matches any word starting with `word`
+}
+
+impl SearchTerm {
+    fn matches(&self, word: &str) -> bool {
+        let word = word.to_lowercase();
+        if self.prefix { word.starts_with(&self.word) } else { word == self.word }
+    }
+}
+
+// Finds the words of a full-text query, skipping the operators and negated words.
+fn search_terms(expression: &str) -> Vec<SearchTerm> {
+    let mut terms = Vec::new();
+    let mut negated = false;
+    for range in words_of(expression) {
+        let word = &expression[range.clone()];
+        // Operators are only recognized outside "quotes":
+        let quoted = expression[..range.start].matches('"').count() % 2 == 1;
+        if !quoted && ["AND", "OR", "NOT", "NEAR"].contains(&word) {
+            negated = word == "NOT";
+        } else if !std::mem::replace(&mut negated, false) {
+            let prefix = expression[range.end..].starts_with('*');
+            terms.push(SearchTerm{word: word.to_lowercase(), prefix});
+        }
+    }
+    terms
+}
+
+// Returns the byte ranges of the words (runs of alphanumeric characters) in the text.
+fn words_of(text: &str) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() || c == '_' {
+            start.get_or_insert(i);
+        } else if let Some(s) = start.take() {
+            words.push(s..i);
+        }
+    }
+    if let Some(s) = start {
+        words.push(s..text.len());
+    }
+    words
+}
+
+// Returns an excerpt of the text around the first match, with the matches highlighted.
+fn snippet(text: &str, words: &[Range<usize>], matches: &[Range<usize>], options: &SearchOptions) -> String {
+    if words.is_empty() {
+        return String::new();
+    }
+    let first = matches.first().and_then(|m| words.iter().position(|w| w == m)).unwrap_or(0);
+    let size = options.snippet_words.max(1);
+    let start = first.saturating_sub((size - 1) / 2).min(words.len().saturating_sub(size));
+    let end = (start + size).min(words.len());
+    let (from, to) = (words[start].start, words[end - 1].end);
+
+    let mut out = String::new();
+    if start > 0 {
+        out.push('…');
+    }
+    let mut pos = from;
+    for m in matches.iter().filter(|m| m.start >= from && m.end <= to) {
+        out += &text[pos..m.start];
+        out += &options.highlight.0;
+        out += &text[m.clone()];
+        out += &options.highlight.1;
+        pos = m.end;
+    }
+    out += &text[pos..to];
+    if end < words.len() {
+        out.push('…');
+    }
+    out
+}
@@ -0,0 +1,380 @@
+// Couchbase Lite document validation
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+
+use std::fmt;
+use std::sync::Arc;
+
+
+/** The document property whose value selects the validator to apply to a document. */
+pub const DOCUMENT_TYPE_PROPERTY : &str = "type";
+
+
+//////// ERRORS:
+
+
+/** A single problem found while validating a document. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationFailure {
+    pub key_path: String,   // Key path of the offending property; empty for the whole document
+    pub message:  String,   // Description of the problem
+}
+
+/** The error returned when a document fails validation. Available from a failed save as
+    `ErrorCode::Validation`. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub doc_id:   String,
+    pub failures: Vec<ValidationFailure>,
+}
+
+impl ValidationFailure {
+    pub fn new<P: Into<String>, M: Into<String>>(key_path: P, message: M) -> ValidationFailure {
+        ValidationFailure{key_path: key_path.into(), message: message.into()}
+    }
+}
+
+impl fmt::Display for ValidationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key_path.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", self.key_path, self.message)
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Document \"{}\" is invalid", self.doc_id)?;
+        for (i, failure) in self.failures.iter().enumerate() {
+            f.write_str(if i == 0 {": "} else {"; "})?;
+            write!(f, "{}", failure)?;
+        }
+        return Ok(());
+    }
+}
+
+
+//////// VALIDATOR:
+
+
+/** Checks a document's properties before the document is saved. Validators are registered with
+    a `Database` per document type, as given by the `DOCUMENT_TYPE_PROPERTY` property.
+
+    Documents are validated when they're saved, and when they're pulled by a `Replicator`: one
+    that fails is rejected before the replicator's `pull_filter` sees it. */
+pub trait Validator : Send + Sync {
+    /** Checks the properties, adding a `ValidationFailure` to `failures` for each problem. */
+    fn validate(&self, properties: Dict, failures: &mut Vec<ValidationFailure>);
+}
+
+impl<F> Validator for F
+    where F: Fn(Dict, &mut Vec<ValidationFailure>) + Send + Sync
+{
+    fn validate(&self, properties: Dict, failures: &mut Vec<ValidationFailure>) {
+        self(properties, failures)
+    }
+}
+
+
+//////// DATABASE'S VALIDATION API:
+
+
+impl Database {
+    /** Registers a validator for documents whose type property equals `doc_type`, replacing
+        any previous one. From then on `save_document` fails with a `ValidationError`, without
+        saving, if a document of that type doesn't pass.
+
+        Validators belong to this `Database` instance, not to the database file: documents saved
+        through another `Database` opened on the same file aren't validated unless the same
+        validators are registered with it too. */
+    pub fn register_validator<V: Validator + 'static>(&self, doc_type: &str, validator: V) {
+        self._extras.validators.write().unwrap().insert(doc_type.to_string(), Arc::new(validator));
+    }
+
+    /** Removes the validator, if any, for a document type. */
+    pub fn unregister_validator(&self, doc_type: &str) {
+        self._extras.validators.write().unwrap().remove(doc_type);
+    }
+
+    /** Checks a document against the validator registered for its type, if any.
+        This is called automatically by `save_document`. */
+    pub fn validate_document(&self, doc: &Document) -> Result<()> {
+        let properties = doc.properties();
+        let validator = match properties.get(DOCUMENT_TYPE_PROPERTY).as_string() {
+            Some(doc_type) => self._extras.validators.read().unwrap().get(doc_type).cloned(),
+            None           => None,
+        };
+        if let Some(validator) = validator {
+            let mut failures = Vec::new();
+            validator.validate(properties, &mut failures);
+            if !failures.is_empty() {
+                return Err(Error::validation_error(ValidationError{doc_id: doc.id().to_string(),
+                                                                   failures}));
+            }
+        }
+        return Ok(());
+    }
+}
+
+
+//////// JSON SCHEMA:
+
+
+/** A `Validator` that checks documents against a JSON Schema.
+    Only a commonly-used subset of the vocabulary is supported: `type`, `enum`, `const`,
+    `required`, `properties`, `additionalProperties` (as a boolean), `items`, `minItems`,
+    `maxItems`, `minLength`, `maxLength`, `minimum`, `maximum`, `exclusiveMinimum` and
+    `exclusiveMaximum`. Other keywords are ignored. */
+pub struct Schema {
+    root: SchemaNode
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SchemaType { Null, Boolean, Integer, Number, String, Array, Object }
+
+#[derive(Default)]
+struct SchemaNode {
+    types:                 Vec<SchemaType>,     // Allowed types; empty means any
+    enum_values:           Option<Vec<String>>, // Allowed values, as JSON
+    required:              Vec<String>,
+    properties:            Vec<(String, SchemaNode)>,
+    additional_properties: Option<bool>,
+    items:                 Option<Box<SchemaNode>>,
+    min_items:             Option<u64>,
+    max_items:             Option<u64>,
+    min_length:            Option<u64>,
+    max_length:            Option<u64>,
+    minimum:               Option<f64>,
+    maximum:               Option<f64>,
+    exclusive_minimum:     Option<f64>,
+    exclusive_maximum:     Option<f64>,
+}
+
+impl Schema {
+    /** Parses a JSON Schema. Returns an `InvalidParameter` error if it isn't valid. */
+    pub fn from_json(json: &str) -> Result<Schema> {
+        let doc = Fleece::parse_json(json)?;
+        return Ok(Schema{root: SchemaNode::parse(doc.root())?});
+    }
+}
+
+impl Validator for Schema {
+    fn validate(&self, properties: Dict, failures: &mut Vec<ValidationFailure>) {
+        self.root.check(properties.as_value(), "", failures);
+    }
+}
+
+impl SchemaType {
+    fn parse(name: &str) -> Result<SchemaType> {
+        match name {
+            "null"    => Ok(SchemaType::Null),
+            "boolean" => Ok(SchemaType::Boolean),
+            "integer" => Ok(SchemaType::Integer),
+            "number"  => Ok(SchemaType::Number),
+            "string"  => Ok(SchemaType::String),
+            "array"   => Ok(SchemaType::Array),
+            "object"  => Ok(SchemaType::Object),
+            _         => Err(invalid_schema()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SchemaType::Null    => "null",
+            SchemaType::Boolean => "boolean",
+            SchemaType::Integer => "integer",
+            SchemaType::Number  => "number",
+            SchemaType::String  => "string",
+            SchemaType::Array   => "array",
+            SchemaType::Object  => "object",
+        }
+    }
+
+    fn matches(&self, value: Value) -> bool {
+        match self {
+            SchemaType::Null    => value.is_type(ValueType::Null),
+            SchemaType::Boolean => value.is_type(ValueType::Bool),
+            SchemaType::Integer => value.is_integer(),
+            SchemaType::Number  => value.is_number(),
+            SchemaType::String  => value.is_type(ValueType::String),
+            SchemaType::Array   => value.is_type(ValueType::Array),
+            SchemaType::Object  => value.is_type(ValueType::Dict),
+        }
+    }
+}
+
+fn invalid_schema() -> Error {
+    Error::cbl_error(CouchbaseLiteError::InvalidParameter)
+}
+
+fn optional_number(schema: Dict, key: &str) -> Result<Option<f64>> {
+    let value = schema.get(key);
+    if !value {
+        return Ok(None);
+    }
+    return value.as_f64().map(Some).ok_or_else(invalid_schema);
+}
+
+fn optional_count(schema: Dict, key: &str) -> Result<Option<u64>> {
+    let value = schema.get(key);
+    if !value {
+        return Ok(None);
+    }
+    return value.as_u64().map(Some).ok_or_else(invalid_schema);
+}
+
+fn child_path(parent: &str, key: &str) -> String {
+    if parent.is_empty() { key.to_string() } else { format!("{}.{}", parent, key) }
+}
+
+impl SchemaNode {
+    fn parse(schema: Value) -> Result<SchemaNode> {
+        if schema.is_type(ValueType::Bool) {
+            // `true` allows anything; `false` allows nothing.
+            let mut node = SchemaNode::default();
+            if !schema.as_bool_or_false() {
+                node.enum_values = Some(Vec::new());
+            }
+            return Ok(node);
+        }
+        let dict = schema.as_dict();
+        if !dict {
+            return Err(invalid_schema());
+        }
+
+        let mut node = SchemaNode::default();
+        let types = dict.get("type");
+        if let Some(name) = types.as_string() {
+            node.types.push(SchemaType::parse(name)?);
+        } else if types.is_type(ValueType::Array) {
+            for t in types.as_array() {
+                node.types.push(SchemaType::parse(t.as_string().ok_or_else(invalid_schema)?)?);
+            }
+        }
+        let enum_values = dict.get("enum");
+        if enum_values.is_type(ValueType::Array) {
+            node.enum_values = Some(enum_values.as_array().iter().map(|v| v.to_json()).collect());
+        }
+        let constant = dict.get("const");
+        if !!constant {
+            node.enum_values = Some(vec![constant.to_json()]);
+        }
+        for name in dict.get("required").as_array() {
+            node.required.push(name.as_string().ok_or_else(invalid_schema)?.to_string());
+        }
+        for (key, property) in dict.get("properties").as_dict() {
+            node.properties.push((key.to_string(), SchemaNode::parse(property)?));
+        }
+        node.additional_properties = dict.get("additionalProperties").as_bool();
+        let items = dict.get("items");
+        if !!items {
+            node.items = Some(Box::new(SchemaNode::parse(items)?));
+        }
+        node.min_items = optional_count(dict, "minItems")?;
+        node.max_items = optional_count(dict, "maxItems")?;
+        node.min_length = optional_count(dict, "minLength")?;
+        node.max_length = optional_count(dict, "maxLength")?;
+        node.minimum = optional_number(dict, "minimum")?;
+        node.maximum = optional_number(dict, "maximum")?;
+        node.exclusive_minimum = optional_number(dict, "exclusiveMinimum")?;
+        node.exclusive_maximum = optional_number(dict, "exclusiveMaximum")?;
+        return Ok(node);
+    }
+
+    fn check(&self, value: Value, path: &str, failures: &mut Vec<ValidationFailure>) {
+        let mut fail = |message: String| failures.push(ValidationFailure::new(path, message));
+
+        if !self.types.is_empty() && !self.types.iter().any(|t| t.matches(value)) {
+            let names: Vec<&str> = self.types.iter().map(|t| t.name()).collect();
+            fail(format!("expected {}, got {}", names.join(" or "), value.to_json()));
+            return;
+        }
+        if let Some(ref allowed) = self.enum_values {
+            let json = value.to_json();
+            if !allowed.contains(&json) {
+                fail(format!("{} is not an allowed value", json));
+            }
+        }
+
+        if let Some(n) = value.as_f64() {
+            if self.minimum.map_or(false, |min| n < min) {
+                fail(format!("{} is less than the minimum of {}", n, self.minimum.unwrap()));
+            }
+            if self.maximum.map_or(false, |max| n > max) {
+                fail(format!("{} is greater than the maximum of {}", n, self.maximum.unwrap()));
+            }
+            if self.exclusive_minimum.map_or(false, |min| n <= min) {
+                fail(format!("{} must be greater than {}", n, self.exclusive_minimum.unwrap()));
+            }
+            if self.exclusive_maximum.map_or(false, |max| n >= max) {
+                fail(format!("{} must be less than {}", n, self.exclusive_maximum.unwrap()));
+            }
+        }
+
+        if let Some(s) = value.as_string() {
+            let length = s.chars().count() as u64;
+            if self.min_length.map_or(false, |min| length < min) {
+                fail(format!("must be at least {} characters long", self.min_length.unwrap()));
+            }
+            if self.max_length.map_or(false, |max| length > max) {
+                fail(format!("must be at most {} characters long", self.max_length.unwrap()));
+            }
+        }
+
+        if value.is_type(ValueType::Array) {
+            let array = value.as_array();
+            let count = array.count() as u64;
+            if self.min_items.map_or(false, |min| count < min) {
+                fail(format!("must have at least {} items", self.min_items.unwrap()));
+            }
+            if self.max_items.map_or(false, |max| count > max) {
+                fail(format!("must have at most {} items", self.max_items.unwrap()));
+            }
+            if let Some(ref items) = self.items {
+                for (i, item) in array.iter().enumerate() {
+                    items.check(item, &format!("{}[{}]", path, i), failures);
+                }
+            }
+        }
+
+        if value.is_type(ValueType::Dict) {
+            let dict = value.as_dict();
+            for key in &self.required {
+                if !dict.get(key) {
+                    failures.push(ValidationFailure::new(child_path(path, key), "is required"));
+                }
+            }
+            for &(ref key, ref property) in &self.properties {
+                let child = dict.get(key);
+                if !!child {
+                    property.check(child, &child_path(path, key), failures);
+                }
+            }
+            if self.additional_properties == Some(false) {
+                for (key, _) in dict {
+                    if !self.properties.iter().any(|&(ref k, _)| k == key) {
+                        failures.push(ValidationFailure::new(child_path(path, key),
+                                                             "is not an allowed property"));
+                    }
+                }
+            }
+        }
+    }
+}
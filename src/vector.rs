@@ -0,0 +1,190 @@
+// Couchbase Lite vector search (Enterprise Edition, 3.2 or later)
+//
+// Copyright (c) 2020 Couchbase, Inc All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+
+use super::*;
+use super::slice::*;
+use super::c_api::*;
+
+use std::mem;
+
+
+/** How the distance between two vectors is measured. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceMetric {
+    EuclideanSquared = 1,   // The default
+    Cosine,                 // 1 - cosine similarity
+    Euclidean,
+    Dot,                    // Negative dot product
+}
+
+impl DistanceMetric {
+    // The metric's name in `APPROX_VECTOR_DISTANCE`.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            DistanceMetric::EuclideanSquared => "EUCLIDEAN_SQUARED",
+            DistanceMetric::Cosine           => "COSINE",
+            DistanceMetric::Euclidean        => "EUCLIDEAN",
+            DistanceMetric::Dot              => "DOT",
+        }
+    }
+}
+
+/** Number of bits per dimension used by a scalar quantizer. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarQuantizerType {
+    SQ4 = 4,
+    SQ6 = 6,
+    SQ8 = 8,
+}
+
+/** How the vectors in an index are compressed. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorEncoding {
+    None,                                               // Full precision: largest, most accurate
+    ScalarQuantizer(ScalarQuantizerType),               // The default is SQ8
+    ProductQuantizer{subquantizers: u32, bits: u32},    // Subquantizers must divide the dimensions
+}
+
+
+/** The configuration of a vector index; see `Database::create_vector_index`. */
+#[derive(Debug, Clone)]
+pub struct VectorIndexConfiguration {
+    pub language:          QueryLanguage,
+    pub expression:        String,          // The vector to index, e.g. "embedding"
+    pub dimensions:        u32,             // Number of dimensions of the vectors, 2...4096
+    pub centroids:         u32,             // Number of buckets the vectors are clustered into
+    pub metric:            DistanceMetric,
+    pub encoding:          VectorEncoding,
+    pub min_training_size: u32,             // Vectors needed to train the index; 0 for default
+    pub max_training_size: u32,             // Vectors used to train the index; 0 for default
+    pub num_probes:        u32,             // Buckets searched per query; 0 for default
+}
+
+impl VectorIndexConfiguration {
+    /** A configuration with the default metric (Euclidean squared), encoding (SQ8) and
+        training parameters. */
+    pub fn new(language: QueryLanguage, expression: &str, dimensions: u32, centroids: u32) -> Self {
+        VectorIndexConfiguration {
+            language,
+            expression:        expression.to_string(),
+            dimensions,
+            centroids,
+            metric:            DistanceMetric::EuclideanSquared,
+            encoding:          VectorEncoding::ScalarQuantizer(ScalarQuantizerType::SQ8),
+            min_training_size: 0,
+            max_training_size: 0,
+            num_probes:        0,
+        }
+    }
+}
+
+
+/** Enables vector search by loading the vector search extension library from a directory.
+    This must be called before opening a database that uses vector indexes.
+    Vector search requires the Enterprise Edition of Couchbase Lite 3.2 or later. */
+pub fn enable_vector_search(extension_dir: &str) -> Result<()> {
+    unsafe {
+        check_bool(|error| CBL_EnableVectorSearch(as_slice(extension_dir), error))
+    }
+}
+
+
+impl Database {
+    /** Creates a vector index, which lets queries find the documents whose vectors are nearest
+        to a target vector with `vector_search::approx_distance`. The indexed vectors may be
+        arrays of numbers, or data as stored by `Slot::put_vector`; documents whose vector is
+        missing or has the wrong number of dimensions aren't indexed.
+
+        Until the database has `min_training_size` vectors the index is untrained, and queries
+        fall back to comparing every vector. Requires `enable_vector_search`.
+
+        The index is created in the database's default collection, so this also depends on the
+        collections API (`CBLDatabase_DefaultCollection`), which older versions of Couchbase Lite
+        don't have. */
+    pub fn create_vector_index(&mut self, name: &str, config: &VectorIndexConfiguration) -> Result<()> {
+        unsafe {
+            let encoding = match config.encoding {
+                VectorEncoding::None =>
+                    CBLVectorEncoding_CreateNone(),
+                VectorEncoding::ScalarQuantizer(bits) =>
+                    CBLVectorEncoding_CreateScalarQuantizer(bits as CBLScalarQuantizerType),
+                VectorEncoding::ProductQuantizer{subquantizers, bits} =>
+                    CBLVectorEncoding_CreateProductQuantizer(subquantizers, bits),
+            };
+            let c_config = CBLVectorIndexConfiguration {
+                expressionLanguage: config.language as CBLQueryLanguage,
+                expression:         as_slice(&config.expression),
+                dimensions:         config.dimensions,
+                centroids:          config.centroids,
+                isLazy:             false,
+                encoding,
+                metric:             config.metric as CBLDistanceMetric,
+                minTrainingSize:    config.min_training_size,
+                maxTrainingSize:    config.max_training_size,
+                numProbes:          config.num_probes,
+            };
+            let result = check_ptr(|error| CBLDatabase_DefaultCollection(self._ref, error),
+                                   |collection| collection)
+                .and_then(|collection| {
+                    let ok = check_bool(|error| CBLCollection_CreateVectorIndex(collection, as_slice(name),
+                                                                               c_config, error));
+                    release(collection);
+                    ok
+                });
+            CBLVectorEncoding_Free(encoding);
+            result?;
+        }
+        self.clear_query_cache();
+        Ok(())
+    }
+}
+
+
+//////// VECTOR VALUES:
+
+
+impl<'s> Slot<'s> {
+    /** Stores a vector as data: its components as consecutive little-endian 32-bit floats.
+        This is more compact than storing it as an array, with `put`. */
+    pub fn put_vector(self, vector: &[f32]) {
+        let mut data = Vec::with_capacity(mem::size_of_val(vector));
+        for component in vector {
+            data.extend_from_slice(&component.to_le_bytes());
+        }
+        self.put_data(data)
+    }
+}
+
+impl<'f> Value<'f> {
+    /** Returns a vector stored as an array of numbers, or as data by `Slot::put_vector`.
+        Returns None if the value is neither. */
+    pub fn as_vector(&self) -> Option<Vec<f32>> {
+        match self.get_type() {
+            ValueType::Array => self.as_array().iter().map(|item| item.as_f32()).collect(),
+            ValueType::Data => {
+                let data = self.as_data()?;
+                if data.len() % mem::size_of::<f32>() != 0 {
+                    return None;
+                }
+                Some(data.chunks(mem::size_of::<f32>())
+                         .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                         .collect())
+            }
+            _ => None,
+        }
+    }
+}
@@ -18,8 +18,10 @@
 #![cfg(test)]
 
 extern crate couchbase_lite;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde_derive;
 
 use couchbase_lite::*;
+use std::collections::HashMap;
 
 #[test]
 fn empty_values() {
@@ -127,3 +129,182 @@ fn mutable_dict() {
     dict.remove("i");
     assert!(!dict.get("i"));
 }
+
+#[test]
+fn key_paths() {
+    let doc = Fleece::parse_json(r#"{"address":{"geo":[{"lat":12.5},{"lat":-3}]},"s":"Foo"}"#).unwrap();
+    let dict = doc.as_dict();
+    assert_eq!(dict.get_path("address.geo[0].lat").unwrap().as_f64(), Some(12.5));
+    assert_eq!(dict.get_path("address.geo[-1].lat").unwrap().as_i64(), Some(-3));
+    assert_eq!(dict.get_path("address.nope").unwrap(), Value::UNDEFINED);
+    assert!(dict.get_path("address.geo[").is_err());
+
+    let path = KeyPath::new("address.geo[1].lat").unwrap();
+    assert_eq!(path.eval(doc.root()).as_i64(), Some(-3));
+    assert_eq!(doc.root().at_path("s").unwrap().as_string(), Some("Foo"));
+}
+
+#[test]
+fn mutable_key_paths() {
+    let mut dict = MutableDict::new();
+    dict.at_path("address.geo[1].lat").unwrap().put_f64(12.5);
+    dict.at_path("address.city").unwrap().put_string("Paris");
+    assert_eq!(dict.to_json(), r#"{"address":{"city":"Paris","geo":[null,{"lat":12.5}]}}"#);
+
+    dict.at_path("address.geo[-1].lon").unwrap().put_i64(7);
+    assert_eq!(dict.get_path("address.geo[1].lon").unwrap().as_i64(), Some(7));
+
+    assert!(dict.at_path("address.city.zip").is_err());
+}
+
+#[test]
+fn json_deltas() {
+    let old = Fleece::parse_json(r#"{"name":"Zegpi","tags":["a","b"],"age":30}"#).unwrap();
+    let new = Fleece::parse_json(r#"{"name":"Zegpi","tags":["a","c"],"city":"Paris"}"#).unwrap();
+
+    let delta = old.root().diff(&new.root());
+    assert!(!delta.is_empty());
+    assert!(old.root().diff(&old.root()).is_empty());
+
+    let patched = old.root().patched(&delta).unwrap();
+    assert_eq!(patched.root(), new.root());
+
+    let mut dict = old.as_dict().mutable_copy();
+    dict.apply_delta(&JsonDelta::from_json(delta.as_json())).unwrap();
+    assert_eq!(dict.to_json(), new.root().to_json());
+}
+
+#[test]
+fn typed_properties() {
+    let doc = Fleece::parse_json(r#"{"i":1234,"big":300,"f":2.5,"s":"Foo","b":true,
+                                     "tags":["a","b",3],"n":null,"m":{"x":1,"y":2}}"#).unwrap();
+    let dict = doc.as_dict();
+    assert_eq!(dict.get_as::<i64>("i"), Ok(1234));
+    assert_eq!(dict.get_as::<f64>("f"), Ok(2.5));
+    assert_eq!(dict.get_as::<&str>("s"), Ok("Foo"));
+    assert_eq!(dict.get_as::<bool>("b"), Ok(true));
+    assert_eq!(dict.get_as::<Option<String>>("n"), Ok(None));
+    assert_eq!(dict.get_as::<Option<i32>>("i"), Ok(Some(1234)));
+    assert_eq!(dict.get_as::<HashMap<String, u8>>("m").unwrap().get("y"), Some(&2));
+
+    assert_eq!(dict.get_as::<i64>("nope"),
+               Err(PropertyError::Missing{key_path: "nope".to_string()}));
+    assert_eq!(dict.get_as::<String>("i"),
+               Err(PropertyError::WrongType{key_path: "i".to_string(), expected: "string",
+                                            actual: ValueType::Number}));
+    assert_eq!(dict.get_as::<u8>("big"),
+               Err(PropertyError::OutOfRange{key_path: "big".to_string(), expected: "u8"}));
+    assert_eq!(dict.get_as::<Vec<String>>("tags").unwrap_err().key_path(), "tags[2]");
+
+    let mut props = MutableDict::new();
+    props.at("i").put(17u64);
+    props.at("s").put("bar");
+    props.at("list").put(vec![1, 2, 3]);
+    props.at("opt").put(None::<i32>);
+    props.at("when").put(Timestamp::from_millis(1000));
+    assert_eq!(props.to_json(), r#"{"i":17,"list":[1,2,3],"opt":null,"s":"bar","when":1000}"#);
+    assert_eq!(props.get_as::<Vec<i64>>("list"), Ok(vec![1, 2, 3]));
+}
+
+#[test]
+fn data_values() {
+    let mut props = MutableDict::new();
+    props.at("d").put_data([1u8, 2, 255]);
+    assert_eq!(props.get("d").get_type(), ValueType::Data);
+    assert_eq!(props.get("d").as_data(), Some(&[1u8, 2, 255][..]));
+}
+
+#[test]
+fn encoder() {
+    let mut props = MutableDict::new();
+    props.at("nested").put(vec![true, false]);
+
+    let mut enc = Encoder::new();
+    enc.begin_dict(4);
+    enc.write_key("name").write_string("Widget");
+    enc.write_key("sizes").begin_array(3).write_i64(-3).write_u64(5).write_f64(7.5).end_array();
+    enc.write_key("copy").write_value(&props);
+    enc.write_key("none").write_null();
+    enc.end_dict();
+    assert!(enc.error().is_none());
+    let doc = enc.finish().expect("finish");
+    assert_eq!(doc.as_dict().to_json(),
+               r#"{"copy":{"nested":[true,false]},"name":"Widget","none":null,"sizes":[-3,5,7.5]}"#);
+
+    let mut json = Encoder::with_format(EncoderFormat::JSON);
+    json.begin_array(0).write_string("a\"b").write_bool(true).end_array();
+    assert_eq!(String::from_utf8(json.finish_data().expect("finish")).unwrap(), r#"["a\"b",true]"#);
+
+    let mut bad = Encoder::new();
+    bad.begin_dict(1).write_i64(1).end_dict();
+    assert!(bad.error().is_some());
+    assert!(bad.finish().is_err());
+
+    // Data encoded with shared keys is smaller, and can be read with a copy of the keys:
+    let keys = SharedKeys::new();
+    let mut enc = Encoder::new();
+    enc.set_shared_keys(&keys);
+    enc.begin_dict(1).write_key("longish_key_name").write_i64(1).end_dict();
+    let data = enc.finish_data().expect("finish");
+    assert_eq!(keys.count(), 1);
+    let mut plain = Encoder::new();
+    plain.begin_dict(1).write_key("longish_key_name").write_i64(1).end_dict();
+    assert!(data.len() < plain.finish_data().expect("finish").len());
+
+    let mut copied_keys = SharedKeys::new();
+    copied_keys.load_state_data(&keys.state_data()).expect("load keys");
+    let doc = Fleece::parse_with_shared_keys(&data, Trust::Untrusted, &copied_keys).expect("parse");
+    assert_eq!(doc.as_dict().get("longish_key_name").as_i64(), Some(1));
+}
+
+#[cfg(feature = "vector-search")]
+#[test]
+fn vector_values() {
+    let mut props = MutableDict::new();
+    props.at("array").put(vec![1.0f32, -0.5, 2.25]);
+    props.at("data").put_vector(&[1.0, -0.5, 2.25]);
+    props.at("bad").put(vec!["x"]);
+    assert_eq!(props.get("data").as_data().map(|d| d.len()), Some(12));
+    assert_eq!(props.get("array").as_vector(), Some(vec![1.0, -0.5, 2.25]));
+    assert_eq!(props.get("data").as_vector(), Some(vec![1.0, -0.5, 2.25]));
+    assert_eq!(props.get("bad").as_vector(), None);
+    assert_eq!(props.get("missing").as_vector(), None);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_values() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Shape { Point, Circle(f64), Pair(i8, i8), Rect{w: u32, h: u32} }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Item { name: String, count: u64, tags: Vec<String>, shapes: Vec<Shape>,
+                  note: Option<String>, scores: HashMap<String, f32> }
+
+    let item = Item{name: "widget".to_string(), count: u64::MAX, tags: vec!["a".to_string()],
+                    shapes: vec![Shape::Point, Shape::Circle(1.5), Shape::Pair(-1, 2), Shape::Rect{w: 3, h: 4}],
+                    note: None, scores: vec![("x".to_string(), 0.5)].into_iter().collect()};
+    let doc = Fleece::from_serializable(&item).expect("encode");
+    let dict = fleece::to_mutable_dict(&item).expect("serialize");
+    assert_eq!(doc.as_dict().to_json(), dict.to_json());
+    assert_eq!(doc.as_dict().get("shapes").to_json(),
+               r#"["Point",{"Circle":1.5},{"Pair":[-1,2]},{"Rect":{"h":4,"w":3}}]"#);
+    assert_eq!(fleece::from_value::<Item>(doc.root()).expect("deserialize"), item);
+    assert_eq!(Fleece::from_serializable(&17).expect("encode").root().as_i64(), Some(17));
+
+    let mut bad = HashMap::new();
+    bad.insert(vec![1], 2);
+    assert!(Fleece::from_serializable(&bad).is_err());
+
+    // Strings and data can be borrowed from the Fleece document, without copying:
+    #[derive(Deserialize)]
+    struct Borrowed<'a> { name: &'a str, data: &'a [u8] }
+
+    let mut props = MutableDict::new();
+    props.at("name").put_string("zero-copy");
+    props.at("data").put_data([1u8, 2, 3]);
+    let doc = Fleece::copy_of(&props).expect("copy");
+    let borrowed: Borrowed = fleece::from_value(doc.root()).expect("deserialize");
+    assert_eq!((borrowed.name, borrowed.data), ("zero-copy", &[1u8, 2, 3][..]));
+    assert_eq!(borrowed.name.as_ptr(), doc.as_dict().get("name").as_string().unwrap().as_ptr());
+}
@@ -16,8 +16,9 @@
 //
 #![allow(unused_imports)]
 
-extern crate couchbase_lite;
+#[macro_use] extern crate couchbase_lite;
 extern crate tempdir;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde_derive;
 
 use couchbase_lite::*;
 use tempdir::TempDir;
@@ -126,6 +127,206 @@ fn save_document() {
     });
 }
 
+#[test]
+fn update_document() {
+    with_db(|db| {
+        add_doc(db, "counter", 1, "one");
+        let doc = db.update_document("counter", 3, |props| {
+            let i = props.get("i").as_i64().unwrap();
+            props.at("i").put_i64(i + 1);
+            Ok(())
+        }).expect("update");
+        assert_eq!(doc.properties().get("i").as_i64(), Some(2));
+        assert_eq!(db.get_document("counter").expect("reload").properties().get("i").as_i64(), Some(2));
+
+        let missing = db.update_document("nope", 3, |_| Ok(()));
+        assert!(missing.is_err());
+    });
+}
+
+#[test]
+fn bulk_documents() {
+    with_db(|db| {
+        let mut docs: Vec<Document> = (0..10).map(|i| {
+            let mut doc = Document::new_with_id(&format!("bulk{}", i));
+            doc.mutable_properties().at("i").put_i64(i);
+            doc
+        }).collect();
+        let results = db.save_documents(&mut docs, ConcurrencyControl::FailOnConflict);
+        assert_eq!(results.len(), 10);
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(db.count(), 10);
+
+        let fetched = db.get_documents(&["bulk3", "nope", "bulk7"]);
+        assert_eq!(fetched[0].as_ref().unwrap().properties().get("i").as_i64(), Some(3));
+        assert!(fetched[1].is_err());
+        assert_eq!(fetched[2].as_ref().unwrap().id(), "bulk7");
+
+        // A stale copy conflicts, which aborts the whole atomic batch:
+        let stale = db.get_document("bulk1").unwrap();
+        db.update_document("bulk1", 0, |props| { props.at("i").put_i64(100); Ok(()) }).unwrap();
+        let mut batch = vec![Document::new_with_id("bulk10"), stale];
+        assert!(db.save_documents_atomically(&mut batch, ConcurrencyControl::FailOnConflict).is_err());
+        assert!(db.get_document("bulk10").is_err());
+
+        let to_delete = vec![db.get_document("bulk2").unwrap(), db.get_document("bulk4").unwrap()];
+        assert!(db.delete_documents(&to_delete, ConcurrencyControl::LastWriteWins)
+                  .iter().all(|r| r.is_ok()));
+        assert_eq!(db.count(), 8);
+    });
+}
+
+#[test]
+fn revision_metadata() {
+    with_db(|db| {
+        add_doc(db, "foo", 1, "one");
+        let doc = db.get_document("foo").unwrap();
+        assert_eq!(doc.generation(), 1);
+        assert!(!doc.is_deleted());
+        assert_eq!(doc.expiration().unwrap(), None);
+
+        let when = Timestamp::now() + std::time::Duration::from_secs(60);
+        db.set_document_expiration("foo", Some(when)).unwrap();
+        assert_eq!(doc.expiration().unwrap(), Some(when));
+        let newer = db.update_document("foo", 0, |props| { props.at("i").put_i64(2); Ok(()) }).unwrap();
+        assert_eq!(newer.generation(), 2);
+        assert_eq!(newer.expiration().unwrap(), Some(when));
+
+        let mut unsaved = Document::new_with_id("bar");
+        assert_eq!(unsaved.expiration().unwrap(), None);
+        db.save_document(&mut unsaved, ConcurrencyControl::FailOnConflict).unwrap();
+        db.set_document_expiration("bar", Some(when)).unwrap();
+        assert_eq!(unsaved.expiration().unwrap(), Some(when));
+        assert!(newer.revision().unwrap() > doc.revision().unwrap());
+
+        db.delete_document(&newer, ConcurrencyControl::FailOnConflict).unwrap();
+        let err = db.get_document("foo").err().expect("deleted");
+        assert_eq!(err.code, ErrorCode::CouchbaseLite(CouchbaseLiteError::NotFound));
+    });
+
+    let tree = RevisionId::new("2-abc");
+    assert_eq!(tree.generation(), Some(2));
+    assert!(RevisionId::new("10-aaa") > RevisionId::new("9-fff"));
+    assert!(RevisionId::new("3-b") > RevisionId::new("3-a"));
+
+    let version = RevisionId::new("17a6f3b5e2c80000@AbCd,1000@Other");
+    assert!(version.is_version_vector());
+    assert_eq!(version.timestamp(), Some(Timestamp::from_millis(0x17a6f3b5e2c80000 / 1_000_000)));
+    assert!(version > tree);
+    assert!(RevisionId::new("17a6f3b5e2c80001@AbCd") > version);
+}
+
+#[test]
+fn timestamps() {
+    let t = Timestamp::from_millis(1_600_000_000_000);
+    let later = t + std::time::Duration::from_secs(60);
+    assert!(later > t);
+    assert_eq!(later.millis(), 1_600_000_060_000);
+    assert_eq!(later.duration_since(t), Some(std::time::Duration::from_secs(60)));
+    assert_eq!(t.duration_since(later), None);
+    assert_eq!(Timestamp::from(std::time::SystemTime::from(t)), t);
+
+    // Out-of-range arithmetic saturates, or returns None from the checked forms:
+    let forever = std::time::Duration::from_secs(u64::MAX);
+    assert_eq!(t.checked_add(forever), None);
+    assert_eq!(t.checked_sub(forever), None);
+    assert_eq!(t + forever, Timestamp::from_millis(i64::MAX));
+    assert_eq!(t - forever, Timestamp::from_millis(i64::MIN));
+    assert_eq!(Timestamp::from_millis(i64::MAX - 1).checked_add(std::time::Duration::from_millis(2)), None);
+    assert_eq!(t.checked_sub(std::time::Duration::from_secs(60)), Some(t - std::time::Duration::from_secs(60)));
+    assert_eq!(Timestamp::from_millis(i64::MAX).duration_since(Timestamp::from_millis(i64::MIN)),
+               Some(std::time::Duration::from_millis(u64::MAX)));
+}
+
+#[test]
+fn document_expiration() {
+    with_db(|db| {
+        add_doc(db, "session", 1, "one");
+        assert_eq!(db.document_expiration("session").expect("get expiration"), None);
+
+        let before = Timestamp::now();
+        db.expire_document_after("session", std::time::Duration::from_secs(24 * 60 * 60))
+          .expect("set expiration");
+        let exp = db.document_expiration("session").expect("get expiration").unwrap();
+        assert!(exp > before);
+        assert!(exp.duration_since(before).unwrap() <= std::time::Duration::from_secs(24 * 60 * 60 + 1));
+    });
+}
+
+#[test]
+fn validation() {
+    with_db(|db| {
+        let schema = Schema::from_json(r#"{
+            "type": "object",
+            "required": ["name"],
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "age":  {"type": "integer", "minimum": 0}
+            }}"#).expect("parse schema");
+        db.register_validator("person", schema);
+
+        let mut doc = Document::new_with_id("p1");
+        {
+            let mut props = doc.mutable_properties();
+            props.at("type").put_string("person");
+            props.at("age").put_i64(-1);
+        }
+        let err = db.save_document(&mut doc, ConcurrencyControl::FailOnConflict).unwrap_err();
+        match err.code {
+            ErrorCode::Validation(ref e) => {
+                assert_eq!(e.doc_id, "p1");
+                let paths: Vec<&str> = e.failures.iter().map(|f| f.key_path.as_str()).collect();
+                assert_eq!(paths, vec!["name", "age"]);
+            },
+            _ => panic!("Unexpected error {:?}", err),
+        }
+        assert_eq!(db.count(), 0);
+
+        doc.mutable_properties().at("name").put_string("Zegpi");
+        doc.mutable_properties().at("age").put_i64(30);
+        db.save_document(&mut doc, ConcurrencyControl::FailOnConflict).expect("save");
+
+        // Documents of other types aren't affected:
+        add_doc(db, "other", -1, "");
+    });
+}
+
+#[test]
+fn id_generators() {
+    let uuid = UuidV4Generator.generate_id();
+    assert_eq!(uuid.len(), 36);
+    assert_eq!(&uuid[14..15], "4");
+
+    let v7 = UuidV7Generator::new();
+    let ids: Vec<String> = (0..100).map(|_| v7.generate_id()).collect();
+    let mut sorted = ids.clone();
+    sorted.sort();
+    assert_eq!(ids, sorted);
+    assert_eq!(&ids[0][14..15], "7");
+
+    let ulid = UlidGenerator::new();
+    let ids: Vec<String> = (0..100).map(|_| ulid.generate_id()).collect();
+    let mut sorted = ids.clone();
+    sorted.sort();
+    assert_eq!(ids, sorted);
+    assert_eq!(ids[0].len(), 26);
+
+    with_db(|db| {
+        add_doc(db, "order::7", 1, "one");
+        db.set_id_generator(CounterIdGenerator::resuming(db, "order").expect("resume"));
+        let doc = db.new_document();
+        assert_eq!(doc.id(), "order::8");
+
+        // Wildcards in the type don't match other types:
+        add_doc(db, "a_b::3", 1, "one");
+        add_doc(db, "axb::9", 1, "one");
+        add_doc(db, "a_b:;5", 1, "one");
+        assert_eq!(CounterIdGenerator::resuming(db, "a_b").expect("resume").generate_id(), "a_b::4");
+        assert_eq!(CounterIdGenerator::resuming(db, "a%").expect("resume").generate_id(), "a%::1");
+        assert_eq!(Document::new_with_generator(&CounterIdGenerator::new("item")).id(), "item::1");
+    });
+}
+
 #[test]
 fn query() {
     with_db(|db| {
@@ -138,35 +339,34 @@ fn query() {
         assert_eq!(query.column_name(0), Some("i"));
         assert_eq!(query.column_name(1), Some("s"));
 
-        // Step through the iterator manually:
-        let results = query.execute().expect("execute");
-        let mut row = (&results).next().unwrap(); //FIXME: Do something about the (&results). requirement
-        let mut i = row.get(0).as_i64().unwrap();
-        let mut s = row.get(1).as_string().unwrap();
-        assert_eq!(i, 2);
-        assert_eq!(s, "two");
-        assert_eq!(row.as_dict().to_json(), r#"{"i":2,"s":"two"}"#);
-
-        row = (&results).next().unwrap();
-        i = row.get(0).as_i64().unwrap();
-        s = row.get(1).as_string().unwrap();
-        assert_eq!(i, 3);
-        assert_eq!(s, "three");
-        assert_eq!(row.as_dict().to_json(), r#"{"i":3,"s":"three"}"#);
-
-        assert!((&results).next().is_none());
-
-        // Now try a for...in loop:
+        // Step through the rows manually:
+        let mut results = query.execute().expect("execute");
+        {
+            let row = results.next_row().unwrap();
+            assert_eq!(row.get(0).as_i64(), Some(2));
+            assert_eq!(row.get(1).as_string(), Some("two"));
+            assert_eq!(row.as_dict().to_json(), r#"{"i":2,"s":"two"}"#);
+        }
+        {
+            let row = results.next_row().unwrap();
+            assert_eq!(row.get(0).as_i64(), Some(3));
+            assert_eq!(row.get(1).as_string(), Some("three"));
+            assert_eq!(row.as_array().to_json(), r#"[3,"three"]"#);
+        }
+        assert!(results.next_row().is_none());
+
+        // Now try a for...in loop, which yields owned rows:
         let mut n = 0;
-        for row in &query.execute().expect("execute") {
+        for row in query.execute().expect("execute") {
+            let row = row.expect("row");
             match n {
                 0 => {
-                    assert_eq!(row.as_array().to_json(), r#"[2,"two"]"#);
-                    assert_eq!(row.as_dict().to_json(), r#"{"i":2,"s":"two"}"#);
+                    assert_eq!(row.get(1).as_string(), Some("two"));
+                    assert_eq!(row.to_json(), r#"{"i":2,"s":"two"}"#);
                 },
                 1 => {
-                    assert_eq!(row.as_array().to_json(), r#"[3,"three"]"#);
-                    assert_eq!(row.as_dict().to_json(), r#"{"i":3,"s":"three"}"#);
+                    assert_eq!(row.get_key("i").as_i64(), Some(3));
+                    assert_eq!(row.to_json(), r#"{"i":3,"s":"three"}"#);
                 },
                 _ => {panic!("Too many rows ({})", n);}
             }
@@ -177,6 +377,566 @@ fn query() {
     });
 }
 
+#[test]
+fn query_results() {
+    with_db(|db| {
+        add_doc(db, "doc-1", 1, "one");
+        add_doc(db, "doc-2", 2, "two");
+        add_doc(db, "doc-3", 3, "three");
+        let query = Query::new(db, QueryLanguage::N1QL, "select i, s from _ order by i").expect("create query");
+
+        let rows: Vec<OwnedRow> = query.execute().expect("execute").collect::<Result<_>>().expect("rows");
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2].get(1).as_string(), Some("three"));
+        assert_eq!(rows[0].column_name(1), Some("s"));
+
+        assert_eq!(query.execute().expect("execute").into_buffered().expect("rows").len(), 3);
+        assert_eq!(query.execute().expect("execute").first().expect("row").unwrap().get(0).as_i64(), Some(1));
+        assert!(query.execute().expect("execute").single().is_err());
+        assert_eq!(query.execute().expect("execute").to_vec_of_json(),
+                   vec![r#"{"i":1,"s":"one"}"#, r#"{"i":2,"s":"two"}"#, r#"{"i":3,"s":"three"}"#]);
+
+        let one = Query::new(db, QueryLanguage::N1QL, "select s from _ where i = 2").expect("create query");
+        assert_eq!(one.execute().expect("execute").single().unwrap().get_key("s").as_string(), Some("two"));
+
+        // Owned rows keep MISSING columns, and index the columns by position:
+        let missing = Query::new(db, QueryLanguage::N1QL, "select i, nope, s from _ where i = 3").expect("create query");
+        let row = missing.execute().expect("execute").single().expect("row");
+        assert!(!row.get(1));
+        assert_eq!(row.get(2).as_string(), Some("three"));
+        assert_eq!(row.get_key("s").as_string(), Some("three"));
+        assert_eq!(row.as_array().count(), 3);
+        assert_eq!(row.to_json(), r#"{"i":3,"s":"three"}"#);
+    });
+}
+
+#[test]
+fn export_results() {
+    with_db(|db| {
+        for (id, i, city) in &[("a", 1, "Paris, FR"), ("b", 2, "Say \"hi\"")] {
+            let mut doc = Document::new_with_id(id);
+            let mut props = doc.mutable_properties();
+            props.at("i").put_i64(*i);
+            props.at("address").put(params!{"city" => *city, "zip" => 75});
+            props.at("tags").put(vec!["x", "y"]);
+            db.save_document(&mut doc, ConcurrencyControl::FailOnConflict).expect("save");
+        }
+        let query = Query::new(db, QueryLanguage::N1QL,
+                               "select i, address, tags from _ order by i").expect("create query");
+        let options = ExportOptions::default();
+
+        let mut csv = Vec::new();
+        assert_eq!(query.execute().expect("execute").write_csv(&mut csv, &options).expect("csv"), 2);
+        assert_eq!(String::from_utf8(csv).unwrap(),
+                   "i,address.city,address.zip,tags\r\n\
+                    1,\"Paris, FR\",75,\"[\"\"x\"\",\"\"y\"\"]\"\r\n\
+                    2,\"Say \"\"hi\"\"\",75,\"[\"\"x\"\",\"\"y\"\"]\"\r\n");
+
+        let mut json = Vec::new();
+        query.execute().expect("execute").write_json(&mut json, &options).expect("json");
+        assert_eq!(String::from_utf8(json).unwrap(),
+                   "[{\"i\":1,\"address\":{\"city\":\"Paris, FR\",\"zip\":75},\"tags\":[\"x\",\"y\"]},\n\
+                    {\"i\":2,\"address\":{\"city\":\"Say \\\"hi\\\"\",\"zip\":75},\"tags\":[\"x\",\"y\"]}\n]\n");
+
+        let mut jsonl = Vec::new();
+        query.execute().expect("execute").write_jsonl(&mut jsonl, &options).expect("jsonl");
+        assert_eq!(String::from_utf8(jsonl).unwrap().lines().count(), 2);
+
+        let empty = Query::new(db, QueryLanguage::N1QL, "select i from _ where i > 5").expect("create query");
+        let mut csv = Vec::new();
+        assert_eq!(empty.execute().expect("execute").write_csv(&mut csv, &options).expect("csv"), 0);
+        assert_eq!(csv, b"i\r\n");
+    });
+}
+
+#[test]
+fn query_streaming() {
+    with_db(|db| {
+        for i in 1..=20 {
+            add_doc(db, &format!("doc-{}", i), i, "x");
+        }
+        let query = Query::new(db, QueryLanguage::N1QL, "select i from _ order by i").expect("create query");
+
+        let (sender, receiver) = std::sync::mpsc::sync_channel(4);
+        let worker_query = query.clone();
+        let worker = std::thread::spawn(move || worker_query.execute_to_channel(sender));
+        let total: i64 = receiver.iter().map(|row| row.get(0).as_i64_or_0()).sum();
+        assert_eq!(total, 210);
+        assert_eq!(worker.join().unwrap().expect("execute"), 20);
+
+        let rows: Vec<i64> = query.execute_in_background(2)
+                                  .map(|row| row.expect("row").get(0).as_i64_or_0())
+                                  .collect();
+        assert_eq!(rows, (1..=20).collect::<Vec<i64>>());
+
+        // Dropping the stream early stops the worker:
+        let mut stream = query.execute_in_background(1);
+        assert_eq!(stream.next().expect("row").expect("row").get_key("i").as_i64(), Some(1));
+        drop(stream);
+
+        // The stream uses the parameters bound when it started:
+        let above = Query::new(db, QueryLanguage::N1QL, "select i from _ where i > $min order by i")
+                        .expect("create query");
+        let stream = above.bind("min", 18).execute_in_background(1);
+        above.bind("min", 0);
+        let rows: Vec<i64> = stream.map(|row| row.expect("row").get(0).as_i64_or_0()).collect();
+        assert_eq!(rows, vec![19, 20]);
+
+        let bad = Query::new(db, QueryLanguage::N1QL, "select i from _ where i > $min").expect("create query");
+        let results: Vec<_> = bad.execute_in_background(2).collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+    });
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn query_streaming_as() {
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Item { i: i64, s: String }
+
+    with_db(|db| {
+        add_doc(db, "doc-1", 1, "one");
+        add_doc(db, "doc-2", 2, "two");
+        let query = Query::new(db, QueryLanguage::N1QL, "select i, s from _ order by i").expect("create query");
+        let items: Vec<Item> = query.execute_in_background_as::<Item>(8)
+                                    .collect::<Result<_>>().expect("deserialize");
+        assert_eq!(items, vec![Item{i: 1, s: "one".to_string()}, Item{i: 2, s: "two".to_string()}]);
+
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        let worker_query = query.clone();
+        let worker = std::thread::spawn(move || worker_query.execute_to_channel_as::<bool>(sender));
+        assert!(receiver.iter().all(|row| row.is_err()));
+        assert_eq!(worker.join().unwrap().expect("execute"), 2);
+    });
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn query_rows_as() {
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Item { i: i64, s: String, missing: Option<bool> }
+
+    with_db(|db| {
+        add_doc(db, "doc-1", 1, "one");
+        add_doc(db, "doc-2", 2, "two");
+        let query = Query::new(db, QueryLanguage::N1QL, "select i, s from _ order by i").expect("create query");
+        let items: Vec<Item> = query.execute().expect("execute").rows_as::<Item>()
+                                    .collect::<Result<_>>().expect("deserialize");
+        assert_eq!(items, vec![Item{i: 1, s: "one".to_string(), missing: None},
+                               Item{i: 2, s: "two".to_string(), missing: None}]);
+    });
+}
+
+#[cfg(feature = "macros")]
+cbl_query!(ItemsFrom, "SELECT meta().id, s AS name, i * 10 AS tens FROM _ WHERE i >= $min ORDER BY i",
+           { id: String, name: String, tens: i64 });
+
+#[cfg(feature = "macros")]
+cbl_query!(JsonItems, r#"{"WHAT": [[".s"], ["AS", [".i"], "n"]], "WHERE": ["<", [".i"], ["$max"]]}"#,
+           { s: Option<String>, n: u8 });
+
+#[cfg(feature = "macros")]
+cbl_query!(Kinds, "SELECT s AS type FROM _ ORDER BY i", { r#type: String });
+
+#[cfg(feature = "macros")]
+#[test]
+fn typed_query_macro() {
+    assert_eq!(ItemsFrom::COLUMNS, &["id", "name", "tens"]);
+    assert_eq!(ItemsFrom::PARAMETERS, &["min"]);
+    assert_eq!(JsonItems::COLUMNS, &["s", "n"]);
+    assert_eq!(JsonItems::PARAMETERS, &["max"]);
+    assert_eq!(Kinds::COLUMNS, &["type"]);
+
+    with_db(|db| {
+        add_doc(db, "doc-1", 1, "one");
+        add_doc(db, "doc-2", 2, "two");
+        add_doc(db, "doc-3", 300, "lots");
+
+        let items = ItemsFrom::new(db).expect("create query");
+        let rows: Vec<ItemsFromRow> = items.set_min(2).execute().expect("execute")
+                                           .collect::<std::result::Result<_, _>>().expect("convert rows");
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].id.as_str(), rows[0].name.as_str(), rows[0].tens), ("doc-2", "two", 20));
+        assert_eq!((rows[1].id.as_str(), rows[1].name.as_str(), rows[1].tens), ("doc-3", "lots", 3000));
+
+        let json = JsonItems::new(db).expect("create query");
+        let rows: Vec<_> = json.set_max(1000).execute().expect("execute").collect();
+        assert_eq!(rows.len(), 3);
+        let err = rows.into_iter().find_map(|row| row.err()).expect("out-of-range row");
+        assert_eq!(err.key_path(), "n");
+
+        let kinds: Vec<KindsRow> = Kinds::new(db).expect("create query").execute().expect("execute")
+                                         .collect::<std::result::Result<_, _>>().expect("convert rows");
+        assert_eq!(kinds[0].r#type, "one");
+    });
+}
+
+#[test]
+fn query_parameters() {
+    with_db(|db| {
+        add_doc(db, "doc-1", 1, "one");
+        add_doc(db, "doc-2", 2, "two");
+        add_doc(db, "doc-3", 3, "three");
+
+        let query = Query::new(db, QueryLanguage::N1QL,
+                               "select s from _ where i >= $min and s != '$notparam' and s in $names")
+                        .expect("create query");
+        assert_eq!(query.parameter_names(), vec!["min", "names"]);
+        assert_eq!(query.unbound_parameters(), vec!["min", "names"]);
+        let err = query.execute().err().expect("unbound parameters");
+        assert_eq!(err.code, ErrorCode::CouchbaseLite(CouchbaseLiteError::InvalidQueryParam));
+
+        query.bind("min", 2);
+        assert_eq!(query.unbound_parameters(), vec!["names"]);
+        query.bind("names", vec!["one", "two"]);
+        assert_eq!(query.execute().expect("execute").count(), 1);
+
+        query.set_parameters(params!{ "min" => 1, "names" => vec!["one", "three"] });
+        assert_eq!(query.execute().expect("execute").count(), 2);
+
+        let json = Query::new(db, QueryLanguage::JSON,
+                              r#"{"WHAT":[[".s"]],"WHERE":[">",[".i"],["$min"]]}"#).expect("create query");
+        assert_eq!(json.parameter_names(), vec!["min"]);
+    });
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn query_parameters_from_serde() {
+    #[derive(Serialize)]
+    struct Params<'a> { min: i64, names: Vec<&'a str> }
+
+    with_db(|db| {
+        add_doc(db, "doc-1", 1, "one");
+        add_doc(db, "doc-2", 2, "two");
+        let query = Query::new(db, QueryLanguage::N1QL, "select s from _ where i >= $min and s in $names")
+                        .expect("create query");
+        query.set_parameters_from(&Params{min: 1, names: vec!["two"]}).expect("set parameters");
+        assert_eq!(query.parameters().to_json(), r#"{"min":1,"names":["two"]}"#);
+        assert_eq!(query.execute().expect("execute").count(), 1);
+        assert!(query.set_parameters_from(&17).is_err());
+    });
+}
+
+#[test]
+fn query_errors() {
+    with_db(|db| {
+        let err = Query::new(db, QueryLanguage::N1QL, "select i\nfrom _ whre i > 1").err().expect("syntax error");
+        assert_eq!(err.code, ErrorCode::CouchbaseLite(CouchbaseLiteError::InvalidQuery));
+        let location = err.query_location().expect("error location").clone();
+        assert_eq!(location.line, 2);
+        assert_eq!(location.snippet, "from _ whre i > 1");
+        assert_eq!(location.offset, 9 + location.column - 1);
+        let message = err.to_string();
+        assert!(message.ends_with(&format!(", at line 2, column {}:\nfrom _ whre i > 1\n{}^",
+                                           location.column, " ".repeat(location.column - 1))));
+    });
+}
+
+#[test]
+fn query_builder() {
+    with_db(|db| {
+        add_doc(db, "doc-1", 1, "one");
+        add_doc(db, "doc-2", 2, "two");
+        add_doc(db, "doc-3", 3, "three");
+
+        let builder = QueryBuilder::select(vec![prop("i"), prop("s"), meta::id().as_("id")])
+                          .from(db)
+                          .where_(prop("i").gt(param("min")).and(prop("s").ne("three")))
+                          .order_by(vec![prop("i").desc()])
+                          .limit(10);
+        assert_eq!(builder.to_json(),
+                   r#"{"WHAT":[[".i"],[".s"],["AS",["._id"],"id"]],"#.to_string() +
+                   r#""WHERE":["AND",[">",[".i"],["$min"]],["!=",[".s"],"three"]],"# +
+                   r#""ORDER_BY":[["DESC",[".i"]]],"LIMIT":10}"#);
+
+        let query = builder.build().expect("build query");
+        assert_eq!(query.column_names(), vec!["i", "s", "id"]);
+        let mut params = MutableDict::new();
+        params.at("min").put_i64(0);
+        query.set_parameters(params);
+        let ids: Vec<String> = query.execute().expect("execute")
+                                    .map(|row| row.expect("row").get(2).as_string().unwrap().to_string())
+                                    .collect();
+        assert_eq!(ids, vec!["doc-2", "doc-1"]);
+
+        let tags = func::any("t", prop("tags"), var("t").eq("x"));
+        assert_eq!(QueryBuilder::select(vec![func::count(meta::id())])
+                       .from_as(db, "a")
+                       .join(Join::left_outer("b").on(prop("a.i").eq(prop("b.i") + 1)))
+                       .where_(!tags)
+                       .group_by(vec![prop("a.s")])
+                       .having(func::count(meta::id()).ge(2))
+                       .to_json(),
+                   r#"{"WHAT":[["COUNT()",["._id"]]],"#.to_string() +
+                   r#""FROM":[{"AS":"a"},{"AS":"b","JOIN":"LEFT OUTER","ON":["=",[".a.i"],["+",[".b.i"],1]]}],"# +
+                   r#""WHERE":["NOT",["ANY","t",[".tags"],["=",["?t"],"x"]]],"# +
+                   r#""GROUP_BY":[[".a.s"]],"HAVING":[">=",["COUNT()",["._id"]],2]}"#);
+
+        // Without an alias, the main data source still comes first in FROM:
+        assert_eq!(QueryBuilder::select(vec![prop("b.s")])
+                       .from(db)
+                       .join(Join::cross("b"))
+                       .to_json(),
+                   r#"{"WHAT":[[".b.s"]],"FROM":[{},{"AS":"b","JOIN":"CROSS"}]}"#);
+    });
+}
+
+#[test]
+fn query_plan() {
+    let plan = QueryPlan::parse(
+        "SELECT fl_result(fl_value(_doc.body, 'name')) FROM kv_default AS _doc \
+         WHERE (fl_value(_doc.body, 'name') = 'x') AND (_doc.flags & 1 = 0)\n\n\
+         3|0|0| SEARCH TABLE kv_default AS _doc USING INDEX byName (<expr>=?)\n\n\
+         {\"WHAT\":[[\".name\"]]}\n");
+    assert_eq!(plan.steps.len(), 1);
+    assert_eq!(plan.steps[0].id, 3);
+    assert_eq!(plan.indexes, vec!["byName"]);
+    assert!(plan.uses_index("byName"));
+    assert!(plan.full_scans().is_empty());
+
+    with_db(|db| {
+        let query = Query::new(db, QueryLanguage::N1QL, "select s from _ where i > 1 and s = 'x'").expect("create query");
+        let plan = query.plan();
+        assert!(plan.sql.starts_with("SELECT"));
+        assert!(plan.indexes.is_empty());
+        let scans = plan.full_scans();
+        assert_eq!(scans.len(), 1);
+        assert_eq!(scans[0].predicates.len(), 2);
+        assert!(std::panic::catch_unwind(|| assert_uses_index(&query, "byName")).is_err());
+    });
+}
+
+#[test]
+fn query_pagination() {
+    with_db(|db| {
+        for i in 1..=5 {
+            add_doc(db, &format!("doc-{}", i), i, &format!("s{}", i));
+        }
+        let page_values = |page: Vec<OwnedRow>| -> Vec<i64> {
+            page.iter().map(|row| row.get(0).as_i64_or_0()).collect()
+        };
+
+        let query = Query::new(db, QueryLanguage::N1QL,
+                               "select i from _ order by i limit $limit offset $offset").expect("create query");
+        let mut pages = query.paginate(2);
+        assert_eq!(page_values(pages.next_page().expect("page").expect("rows")), vec![1, 2]);
+        let token = pages.token();
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        let rest: Vec<Vec<i64>> = query.resume_pages(&token).expect("resume")
+                                       .map(|page| page_values(page.expect("page")))
+                                       .collect();
+        assert_eq!(rest, vec![vec![3, 4], vec![5]]);
+
+        let query = Query::new(db, QueryLanguage::N1QL,
+                               "select i, s from _ where $after is null or s > $after order by s limit $limit").expect("create query");
+        let mut pages = query.paginate_by_key(3, "s");
+        assert_eq!(page_values(pages.next_page().expect("page").expect("rows")), vec![1, 2, 3]);
+        let mut pages = query.resume_pages(&pages.token()).expect("resume");
+        assert_eq!(page_values(pages.next_page().expect("page").expect("rows")), vec![4, 5]);
+        assert!(!pages.has_more());
+        assert!(pages.next_page().expect("page").is_none());
+
+        assert!(query.paginate(2).next_page().is_err());
+        assert!(query.resume_pages("not a token").is_err());
+    });
+}
+
+#[test]
+fn query_cache_and_indexes() {
+    with_db(|db| {
+        add_doc(db, "doc-1", 1, "one");
+        let source = "select i from _ where s = 'one'";
+        db.cached_query(QueryLanguage::N1QL, source).expect("compile");
+        db.cached_query(QueryLanguage::N1QL, source).expect("cached");
+        let stats = db.query_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.size), (1, 1, 1));
+        assert!(db.cached_query(QueryLanguage::N1QL, "select i frm _").is_err());
+
+        // Queries with parameters aren't cached, so their parameters aren't shared:
+        let with_param = "select i from _ where s = $s";
+        let q1 = db.cached_query(QueryLanguage::N1QL, with_param).expect("compile");
+        let q2 = db.cached_query(QueryLanguage::N1QL, with_param).expect("compile again");
+        q1.bind("s", "one");
+        assert_eq!(q2.unbound_parameters(), vec!["s"]);
+        assert_eq!(db.query_cache_stats().size, 1);
+        drop((q1, q2));
+
+        db.create_value_index("byS", QueryLanguage::N1QL, "s").expect("create index");
+        assert_eq!(db.index_names(), vec!["byS"]);
+        assert_eq!(db.query_cache_stats().size, 0);
+        let query = db.cached_query(QueryLanguage::N1QL, source).expect("recompile");
+        assert_uses_index(&query, "byS");
+        drop(query);
+
+        db.set_query_cache_capacity(1);
+        db.cached_query(QueryLanguage::N1QL, "select s from _").expect("compile");
+        let stats = db.query_cache_stats();
+        assert_eq!((stats.size, stats.evictions), (1, 1));
+
+        db.delete_index("byS").expect("delete index");
+        assert!(db.index_names().is_empty());
+        assert_eq!(db.query_cache_stats().size, 0);
+    });
+}
+
+#[test]
+fn full_text_search() {
+    with_db(|db| {
+        add_doc(db, "a", 1, "The quick brown fox jumps over the lazy dog");
+        add_doc(db, "b", 2, "A database is a quick way to store data");
+        add_doc(db, "c", 3, "Nothing to see here");
+        add_doc(db, "d", 4, "Stay near the fire");
+        db.create_full_text_index("text", QueryLanguage::N1QL, "s",
+                                  &FullTextIndexOptions{language: Some("en".to_string()), ..Default::default()})
+          .expect("create index");
+        assert!(db.index_names().contains(&"text".to_string()));
+
+        let results = db.search("text", "quick", &SearchOptions::default()).expect("search");
+        let mut ids = results.ids();
+        ids.sort();
+        assert_eq!(ids, vec!["a", "b"]);
+
+        let options = SearchOptions{mode: SearchMode::Phrase, text_path: Some("s".to_string()),
+                                    snippet_words: 4, ..Default::default()};
+        let results = db.search("text", "brown fox", &options).expect("search");
+        assert_eq!(results.match_expression, "\"brown fox\"");
+        assert_eq!(results.ids(), vec!["a"]);
+        let hit = &results.hits[0];
+        assert_eq!(hit.matches, vec![10..15, 16..19]);
+        assert_eq!(hit.snippet.as_ref().unwrap(), "…quick <b>brown</b> <b>fox</b> jumps…");
+
+        let options = SearchOptions{mode: SearchMode::Prefix, ..Default::default()};
+        let results = db.search("text", "dat", &options).expect("search");
+        assert_eq!(results.match_expression, "\"dat*\"");
+        assert_eq!(results.ids(), vec!["b"]);
+
+        // Operator words in the search text are searched for, not treated as operators:
+        let options = SearchOptions{text_path: Some("s".to_string()), ..Default::default()};
+        let results = db.search("text", "NEAR fire", &options).expect("search");
+        assert_eq!(results.match_expression, "\"NEAR\" \"fire\"");
+        assert_eq!(results.ids(), vec!["d"]);
+        assert_eq!(results.hits[0].matches, vec![5..9, 14..18]);
+        let options = SearchOptions{mode: SearchMode::AnyWord, ..Default::default()};
+        let results = db.search("text", "fox NEAR", &options).expect("search");
+        assert_eq!(results.match_expression, "\"fox\" OR \"NEAR\"");
+        let mut ids = results.ids();
+        ids.sort();
+        assert_eq!(ids, vec!["a", "d"]);
+        let options = SearchOptions{mode: SearchMode::Boolean, ..Default::default()};
+        assert_eq!(db.search("text", "quick NOT fox", &options).expect("search").ids(), vec!["b"]);
+        assert!(db.search("text", "  ", &SearchOptions::default()).expect("search").is_empty());
+    });
+}
+
+#[cfg(feature = "vector-search")]
+#[test]
+fn vector_index() {
+    // The extension is in the directory named by $CBL_EXTENSION_PATH, if it's not in the default:
+    if let Ok(path) = std::env::var("CBL_EXTENSION_PATH") {
+        enable_vector_search(&path).expect("enable vector search");
+    }
+    with_db(|db| {
+        for i in 0..30 {
+            let angle = i as f32 / 30.0 * std::f32::consts::FRAC_PI_2;
+            let mut doc = Document::new_with_id(&format!("doc-{}", i));
+            let mut props = doc.mutable_properties();
+            if i % 2 == 0 {
+                props.at("vector").put(vec![angle.cos(), angle.sin(), 0.0]);
+            } else {
+                props.at("vector").put_vector(&[angle.cos(), angle.sin(), 0.0]);
+            }
+            db.save_document(&mut doc, ConcurrencyControl::FailOnConflict).expect("save");
+        }
+
+        let mut config = VectorIndexConfiguration::new(QueryLanguage::N1QL, "vector", 3, 1);
+        config.metric = DistanceMetric::Cosine;
+        config.encoding = VectorEncoding::None;
+        db.create_vector_index("vectors", &config).expect("create index");
+        assert!(db.index_names().contains(&"vectors".to_string()));
+
+        let distance = vector_search::approx_distance(prop("vector"), param("target"));
+        let builder = QueryBuilder::select(vec![meta::id()])
+                          .from(db)
+                          .order_by(vec![distance.clone()])
+                          .limit(3);
+        assert_eq!(builder.to_json(),
+                   r#"{"WHAT":[["._id"]],"ORDER_BY":[["ASC",["APPROX_VECTOR_DISTANCE()",[".vector"],["$target"]]]],"LIMIT":3}"#);
+        let query = builder.build().expect("build query");
+        query.bind("target", vec![0.0f32, 1.0, 0.0]);
+        let ids: Vec<String> = query.execute().expect("execute")
+                                    .map(|row| row.expect("row"))
+                                    .map(|row| row.get(0).as_string().unwrap().to_string())
+                                    .collect();
+        assert_eq!(ids, vec!["doc-29", "doc-28", "doc-27"]);
+
+        assert_eq!(vector_search::approx_distance_with(prop("vector"), param("target"),
+                                                       DistanceMetric::Cosine, Some(4)),
+                   func::call("APPROX_VECTOR_DISTANCE", vec![prop("vector"), param("target"),
+                                                             val("COSINE"), val(4)]));
+        assert_eq!(vector_search::approx_distance_with(prop("vector"), param("target"),
+                                                       DistanceMetric::Cosine, None),
+                   func::call("APPROX_VECTOR_DISTANCE", vec![prop("vector"), param("target"),
+                                                             val("COSINE")]));
+    });
+}
+
+#[cfg(feature = "enterprise")]
+#[test]
+fn predictive_model() {
+    register_model("double", |input: &Dict| {
+        let n = input.get("n").as_i64()?;
+        let mut output = MutableDict::new();
+        output.at("result").put_i64(2 * n);
+        Some(output)
+    });
+    register_model("broken", |_: &Dict| -> Option<MutableDict> { panic!("broken model") });
+
+    with_db(|db| {
+        add_doc(db, "doc-1", 1, "one");
+        add_doc(db, "doc-2", 2, "two");
+
+        let input = Expression::dict(vec![("n", prop("i"))]);
+        let builder = QueryBuilder::select(vec![
+                            func::prediction_property("double", input.clone(), "result").as_("doubled"),
+                            func::prediction("broken", input).as_("broken")])
+                          .from(db)
+                          .order_by(vec![prop("i")]);
+        assert_eq!(builder.to_json(),
+                   r#"{"WHAT":[["AS",["PREDICTION()","double",{"n":[".i"]},".result"],"doubled"],"#.to_string() +
+                   r#"["AS",["PREDICTION()","broken",{"n":[".i"]}],"broken"]],"ORDER_BY":[["ASC",[".i"]]]}"#);
+        let query = builder.build().expect("build query");
+        let rows: Vec<(Option<i64>, bool)> = query.execute().expect("execute")
+                                                  .map(|row| row.expect("row"))
+                                                  .map(|row| (row.get(0).as_i64(), !row.get(1)))
+                                                  .collect();
+        assert_eq!(rows, vec![(Some(2), true), (Some(4), true)]);
+    });
+
+    unregister_model("double");
+    unregister_model("broken");
+}
+
+#[test]
+fn live_query() {
+    with_db(|db| {
+        add_doc(db, "doc-1", 1, "one");
+        let query = Query::new(db, QueryLanguage::N1QL, "select s from _ order by i").expect("create query");
+        let observer = query.observe();
+        let timeout = std::time::Duration::from_secs(5);
+
+        let results = observer.next_timeout(timeout).expect("initial results").expect("results");
+        assert_eq!(results.count(), 1);
+
+        add_doc(db, "doc-2", 2, "two");
+        let results = observer.next_timeout(timeout).expect("changed results").expect("results");
+        let strings: Vec<String> = results.map(|row| row.expect("row").get(0).as_string().unwrap().to_string())
+                                          .collect();
+        assert_eq!(strings, vec!["one", "two"]);
+    });
+}
+
 
 /*
 // This test doesn't and shouldn't compile -- it tests that the borrow-checker will correctly